@@ -0,0 +1,86 @@
+//! Runs Lox from Rust: passes values in through globals and native functions,
+//! captures what the script prints and reads back the value of its last
+//! expression. Pass `--vm` to run it on the bytecode virtual machine.
+
+use rust_lox::runtime::{Arity, NativeModule};
+use rust_lox::{Literal, Lox};
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// A writer whose contents stay readable after it is handed to the engine.
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let mut lox = if env::args().any(|arg| arg == "--vm") {
+        Lox::with_vm()
+    } else {
+        Lox::new()
+    };
+    let output = Capture::default();
+    lox.set_output(output.clone());
+
+    lox.set_global("limit", Literal::Number(5.0))
+        .expect("numbers can always be passed in");
+
+    lox.define_native("sum", Arity::AtLeast(0), |args| {
+        let numbers = args.rest::<f64>(0)?;
+        Ok(Literal::Number(numbers.iter().sum()))
+    });
+    let text = NativeModule::new("text")
+        .function("repeat", 2, |args| {
+            let count = args.get::<i64>(1)?;
+            if count < 0 {
+                return Err("Count must not be negative.".into());
+            }
+            Ok(Literal::String(
+                args.get::<String>(0)?.repeat(count as usize),
+            ))
+        })
+        .constant("separator", Literal::String(String::from(", ")));
+    lox.define_module(text)
+        .expect("strings can always be passed in");
+
+    let source = "
+        var squares = [];
+        for (var i = 1; i <= limit; i = i + 1) {
+            push(squares, i * i);
+        }
+        print squares;
+        print repeat(\"-\", 10);
+        print sum(1, 2, 3) + separator + \"done\";
+        len(squares);
+    ";
+    match lox.eval(source) {
+        Ok(value) => println!("result: {}", value),
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    }
+
+    print!("printed: {}", String::from_utf8_lossy(&output.0.borrow()));
+    if let Some(squares) = lox.get_global("squares") {
+        println!("squares: {}", squares);
+    }
+
+    if let Err(err) = lox.eval("repeat(\"x\", 1.5);") {
+        println!("error (exit code {}):\n{}", err.exit_code(), err);
+    }
+    if let Err(err) = lox.eval("print 1 / 0;") {
+        println!("error (exit code {}):\n{}", err.exit_code(), err);
+    }
+}
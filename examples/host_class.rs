@@ -0,0 +1,93 @@
+//! Exposes a Rust struct to Lox as a native class: a key-value store whose
+//! instances own a `HashMap`, which a script subclasses and calls methods on.
+//! Pass `--vm` to run it on the bytecode virtual machine.
+
+use rust_lox::runtime::NativeClass;
+use rust_lox::{Literal, Lox};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+
+/// The host data of each `Store` instance.
+struct Store {
+    name: String,
+    entries: RefCell<HashMap<String, Literal>>,
+}
+
+fn store_class() -> NativeClass {
+    NativeClass::new("Store")
+        .method("init", 1, |this, args| {
+            this.set_data(Store {
+                name: args.get::<String>(0)?,
+                entries: RefCell::new(HashMap::new()),
+            });
+            Ok(Literal::Nil)
+        })
+        .method("name", 0, |this, _| {
+            Ok(Literal::String(this.data::<Store>()?.name.clone()))
+        })
+        .method("get", 2, |this, args| {
+            let store = this.data::<Store>()?;
+            let key = args.get::<String>(0)?;
+            let value = store.entries.borrow().get(&key).cloned();
+            Ok(value.unwrap_or_else(|| args.value(1).clone()))
+        })
+        .method("set", 2, |this, args| {
+            let store = this.data::<Store>()?;
+            let key = args.get::<String>(0)?;
+            store
+                .entries
+                .borrow_mut()
+                .insert(key, args.value(1).clone());
+            Ok(Literal::Nil)
+        })
+        .method("size", 0, |this, _| {
+            let size = this.data::<Store>()?.entries.borrow().len();
+            Ok(Literal::Number(size as f64))
+        })
+}
+
+fn main() {
+    let mut lox = if env::args().any(|arg| arg == "--vm") {
+        Lox::with_vm()
+    } else {
+        Lox::new()
+    };
+    lox.define_class(store_class());
+
+    let source = "
+        class Counter < Store {
+            init(name) {
+                super.init(name);
+                this.hits = 0;
+            }
+
+            increment(key) {
+                this.hits = this.hits + 1;
+                this.set(key, this.get(key, 0) + 1);
+            }
+        }
+
+        var counter = Counter(\"words\");
+        counter.increment(\"lox\");
+        counter.increment(\"lox\");
+        counter.increment(\"rust\");
+        print counter;
+        print counter.name() + \": \" + counter.get(\"lox\", 0) + \" lox, \" + counter.size() + \" keys\";
+        counter.hits;
+    ";
+    match lox.eval(source) {
+        Ok(value) => println!("hits: {}", value),
+        Err(err) => println!("error:\n{}", err),
+    }
+
+    let source = "
+        class Broken < Store {
+            init() {}
+        }
+        Broken().size();
+    ";
+    if let Err(err) = lox.eval(source) {
+        println!("error (exit code {}):\n{}", err.exit_code(), err);
+    }
+}
@@ -0,0 +1,43 @@
+//! Runs untrusted scripts in a sandbox: only the core, math and string
+//! modules are defined, and every script is stopped when it runs too long,
+//! recurses too deeply or allocates too much, even inside a `try` statement.
+//! Pass `--vm` to run them on the bytecode virtual machine.
+
+use rust_lox::{Lox, LoxError, Sandbox};
+use std::env;
+use std::time::Duration;
+
+fn main() {
+    let sandbox = Sandbox::new()
+        .allow_module("core")
+        .allow_module("math")
+        .allow_module("string")
+        .max_instructions(100_000)
+        .max_call_depth(50)
+        .max_time(Duration::from_secs(1))
+        .max_allocations(1_000);
+    let mut lox = if env::args().any(|arg| arg == "--vm") {
+        Lox::sandboxed_vm(&sandbox)
+    } else {
+        Lox::sandboxed(&sandbox)
+    };
+
+    let scripts = [
+        "print upper(\"allowed\") + \" \" + str(sqrt(16));",
+        "print read_file(\"/etc/passwd\");",
+        "import \"secrets.lox\";",
+        "try { while (true) {} } catch (error) { print \"caught\"; }",
+        "fun down(n) { return down(n + 1); } down(0);",
+        "var keep = []; while (true) { push(keep, [1]); }",
+        "print \"each run gets a new budget\";",
+    ];
+    for script in scripts {
+        match lox.eval(script) {
+            Ok(_) => {}
+            Err(LoxError::Limit { error, .. }) => {
+                println!("stopped: {}", error.message);
+            }
+            Err(err) => println!("error: {}", err.to_string().lines().next().unwrap_or("")),
+        }
+    }
+}
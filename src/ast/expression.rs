@@ -1,6 +1,7 @@
-use super::Literal;
 use super::{BinaryOperator, LogicalOperator, Statement, TernaryOperator, Token, UnaryOperator};
+use super::{Literal, Span};
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub enum Expression {
@@ -15,6 +16,23 @@ pub enum Expression {
     CallExpression(Box<ExpressionNode>, Token, Vec<ExpressionNode>),
     GetExpression(Identifier, Box<ExpressionNode>),
     SetExpression(Box<ExpressionNode>, Identifier, Box<ExpressionNode>),
+    IndexGetExpression(Box<ExpressionNode>, Token, Box<ExpressionNode>),
+    IndexSetExpression(
+        Box<ExpressionNode>,
+        Token,
+        Box<ExpressionNode>,
+        Box<ExpressionNode>,
+    ),
+    SliceExpression(
+        Box<ExpressionNode>,
+        Token,
+        Option<Box<ExpressionNode>>,
+        Option<Box<ExpressionNode>>,
+    ),
+    ListExpression(Vec<ExpressionNode>),
+    MapExpression(Vec<(ExpressionNode, ExpressionNode)>),
+    This(Variable),
+    Super(Variable, Identifier),
     FunctionExpression(FunctionExpression),
     Literal(Literal),
     Unary(UnaryOperator, Box<ExpressionNode>),
@@ -46,6 +64,38 @@ impl Display for Expression {
             Expression::SetExpression(object, name, value) => {
                 write!(f, "(set {} {} {})", object, name.get_name(), value)
             }
+            Expression::IndexGetExpression(object, _bracket, index) => {
+                write!(f, "(index {} {})", object, index)
+            }
+            Expression::IndexSetExpression(object, _bracket, index, value) => {
+                write!(f, "(index-set {} {} {})", object, index, value)
+            }
+            Expression::SliceExpression(object, _bracket, start, end) => {
+                write!(f, "(slice {}", object)?;
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => write!(f, " {}", bound)?,
+                        None => write!(f, " _")?,
+                    }
+                }
+                write!(f, ")")
+            }
+            Expression::ListExpression(elements) => {
+                write!(f, "(list")?;
+                for element in elements {
+                    write!(f, " {}", element)?;
+                }
+                write!(f, ")")
+            }
+            Expression::MapExpression(entries) => {
+                write!(f, "(map")?;
+                for (key, value) in entries {
+                    write!(f, " ({} {})", key, value)?;
+                }
+                write!(f, ")")
+            }
+            Expression::This(_) => write!(f, "this"),
+            Expression::Super(_, method) => write!(f, "(super {})", method.get_name()),
             Expression::Literal(val) => write!(f, "{}", val),
             Expression::Unary(operator, right) => write!(f, "({} {})", operator, right),
             Expression::Variable(variable) => write!(f, "{}", variable.get_identifier().get_name()),
@@ -60,15 +110,16 @@ impl Display for Expression {
     }
 }
 
+/// An expression together with the span of source text it was parsed from.
 #[derive(Debug, Clone)]
 pub struct ExpressionNode {
-    line: usize,
+    span: Span,
     expr: Expression,
 }
 
 impl ExpressionNode {
-    pub fn new(line: usize, expr: Expression) -> Self {
-        Self { line, expr }
+    pub fn new(span: Span, expr: Expression) -> Self {
+        Self { span, expr }
     }
 
     pub fn expr(&self) -> &Expression {
@@ -79,8 +130,8 @@ impl ExpressionNode {
         &mut self.expr
     }
 
-    pub fn line(&self) -> usize {
-        self.line
+    pub fn span(&self) -> Span {
+        self.span
     }
 }
 
@@ -94,6 +145,7 @@ impl Display for ExpressionNode {
 pub struct Identifier {
     name: String,
     line: usize,
+    span: Span,
 }
 
 impl Identifier {
@@ -101,6 +153,7 @@ impl Identifier {
         Self {
             name: token.lexeme,
             line: token.line,
+            span: token.span,
         }
     }
 
@@ -111,12 +164,17 @@ impl Identifier {
     pub fn get_line(&self) -> usize {
         self.line
     }
+
+    pub fn get_span(&self) -> Span {
+        self.span
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Variable {
     identifier: Identifier,
     depth: Option<usize>,
+    slot: Option<usize>,
 }
 
 impl Variable {
@@ -124,6 +182,7 @@ impl Variable {
         return Self {
             identifier,
             depth: None,
+            slot: None,
         };
     }
 
@@ -135,8 +194,14 @@ impl Variable {
         self.depth
     }
 
-    pub fn set_depth(&mut self, depth: usize) {
-        self.depth = Some(depth)
+    pub fn get_slot(&self) -> Option<usize> {
+        self.slot
+    }
+
+    /// Records that the variable lives `depth` scopes up, at index `slot` of that scope.
+    pub fn resolve(&mut self, depth: usize, slot: usize) {
+        self.depth = Some(depth);
+        self.slot = Some(slot);
     }
 }
 
@@ -150,6 +215,8 @@ pub trait FunctionInfo {
     fn identifier(&self) -> String;
     fn parameters(&self) -> &Vec<Identifier>;
     fn body(&self) -> &Vec<Statement>;
+    /// The text the function was parsed from, which its spans point into.
+    fn source(&self) -> &Rc<str>;
 }
 
 impl FunctionInfo for Function {
@@ -173,6 +240,13 @@ impl FunctionInfo for Function {
             Function::Expression(func) => &func.body,
         }
     }
+
+    fn source(&self) -> &Rc<str> {
+        match &self {
+            Function::Declaration(func) => &func.source,
+            Function::Expression(func) => &func.source,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -180,14 +254,22 @@ pub struct FunctionDeclaration {
     pub identifier: Identifier,
     pub parameters: Vec<Identifier>,
     pub body: Vec<Statement>,
+    /// Keeps the source alive for the errors raised in the function.
+    pub source: Rc<str>,
 }
 
 impl FunctionDeclaration {
-    pub fn new(identifier: Identifier, parameters: Vec<Identifier>, body: Vec<Statement>) -> Self {
+    pub fn new(
+        identifier: Identifier,
+        parameters: Vec<Identifier>,
+        body: Vec<Statement>,
+        source: Rc<str>,
+    ) -> Self {
         Self {
             identifier,
             parameters,
             body,
+            source,
         }
     }
     pub fn get_identifier(&self) -> String {
@@ -221,17 +303,27 @@ impl FunctionInfo for FunctionDeclaration {
     fn body(&self) -> &Vec<Statement> {
         self.get_body()
     }
+
+    fn source(&self) -> &Rc<str> {
+        &self.source
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionExpression {
     pub parameters: Vec<Identifier>,
     pub body: Vec<Statement>,
+    /// Keeps the source alive for the errors raised in the function.
+    pub source: Rc<str>,
 }
 
 impl FunctionExpression {
-    pub fn new(parameters: Vec<Identifier>, body: Vec<Statement>) -> Self {
-        Self { parameters, body }
+    pub fn new(parameters: Vec<Identifier>, body: Vec<Statement>, source: Rc<str>) -> Self {
+        Self {
+            parameters,
+            body,
+            source,
+        }
     }
 
     pub fn get_identifier(&self) -> String {
@@ -269,4 +361,8 @@ impl FunctionInfo for FunctionExpression {
     fn body(&self) -> &Vec<Statement> {
         self.get_body()
     }
+
+    fn source(&self) -> &Rc<str> {
+        &self.source
+    }
 }
@@ -3,7 +3,7 @@ pub mod operators;
 pub mod parser;
 pub mod statement;
 
-pub use super::common::{Error, Literal};
+pub use super::common::{Error, Literal, Span};
 pub use super::scanner::{Token, TokenType};
 pub use expression::*;
 pub use operators::*;
@@ -1,6 +1,7 @@
 use super::Error;
 use super::*;
 use super::{FunctionDeclaration, Identifier, Literal, Token, TokenType, Variable};
+use std::rc::Rc;
 
 type ParseResult<T> = Result<T, ParseError>;
 
@@ -9,50 +10,70 @@ pub enum ParseError {
     MissingExpr(Token),
     MissingVariableName(Token),
     CallArgumentSize(Token),
+    InvalidAssignmentTarget(Token),
+    Expected(Token, String),
 }
 
 impl ParseError {
-    fn print(&self) {
+    fn into_error(self) -> Error {
         let (token, message) = match self {
-            ParseError::MissingExpr(token) => (token, String::from("Expect expression")),
-            ParseError::MissingVariableName(token) => (token, String::from("Expect variable name")),
+            ParseError::MissingExpr(token) => (token, String::from("Expect expression.")),
+            ParseError::MissingVariableName(token) => {
+                (token, String::from("Expect variable name."))
+            }
             ParseError::CallArgumentSize(token) => {
-                (token, String::from("Can't have more than 255 arguments"))
+                (token, String::from("Can't have more than 255 arguments."))
+            }
+            ParseError::InvalidAssignmentTarget(token) => {
+                (token, String::from("Invalid assignment target."))
             }
+            ParseError::Expected(token, message) => (token, message),
+        };
+        let location = match token.typ {
+            TokenType::EOF => String::from("at end"),
+            _ => format!("at '{}'", token.lexeme),
         };
-        eprintln!(
-            "[line {}] Error at {}: {}.",
-            token.line, token.lexeme, message
-        );
+        Error::at(token.span, location, message)
     }
 }
 
+/// Parses tokens into statements. Parsing carries on after an error, so
+/// `errors` ends up holding every syntax error in the program.
 pub struct Parser {
     pub tokens: Vec<Token>,
+    pub errors: Vec<Error>,
     current_token: usize,
+    /// How many blocks enclose the current token.
+    block_depth: usize,
+    /// The text the tokens were scanned from, which the functions parsed
+    /// keep alive.
+    source: Rc<str>,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<Token>, source: Rc<str>) -> Self {
         Self {
             tokens,
+            errors: vec![],
             current_token: 0,
+            block_depth: 0,
+            source,
         }
     }
     pub fn parse(&mut self) -> Vec<Statement> {
         let mut statements: Vec<Statement> = vec![];
         while !self.is_at_end() {
-            match self.declaration() {
-                Ok(expr) => statements.push(expr),
-                Err(e) => {
-                    e.print();
-                }
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
             }
         }
         statements
     }
 
-    fn declaration(&mut self) -> ParseResult<Statement> {
+    /// Parses a declaration, or records the error and skips to the next
+    /// statement if it is malformed.
+    fn declaration(&mut self) -> Option<Statement> {
+        let start = self.current_token;
         let statement = match self.peek().typ {
             TokenType::VAR => {
                 self.advance();
@@ -66,89 +87,105 @@ impl Parser {
                 self.advance();
                 self.class_declaration()
             }
+            TokenType::IMPORT => {
+                let keyword = self.advance();
+                self.import_declaration(keyword)
+            }
             _ => self.statement(),
         };
 
-        if statement.is_err() {
-            self.synchronize();
+        match statement {
+            Ok(statement) => Some(statement),
+            Err(err) => {
+                self.errors.push(err.into_error());
+                self.synchronize();
+                // A stray token that can't start anything, such as a `}` at the
+                // top level, is skipped so that parsing makes progress.
+                if self.current_token == start {
+                    self.advance();
+                }
+                None
+            }
         }
-
-        statement
     }
 
     fn var_declaration(&mut self) -> ParseResult<Statement> {
-        let var_name = self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."));
+        if !self.check_token_type(TokenType::IDENTIFIER) {
+            return Err(ParseError::MissingVariableName(self.peek()));
+        }
+        let name = self.advance();
         let mut init = None;
         if self.peek().typ == TokenType::EQUAL {
             self.advance();
-            init = Some(self.expression()?);
+            init = Some(Box::new(self.expression()?));
         }
         self.consume(
             TokenType::SEMICOLON,
             String::from("Expect ';' after variable declaration."),
-        );
-        match (var_name, init) {
-            (Some(name), Some(expr)) => {
-                return Ok(Statement::VariableDeclaration(
-                    Identifier::token_to_id(name),
-                    Some(Box::new(expr)),
-                ))
-            }
-            (Some(name), None) => {
-                return Ok(Statement::VariableDeclaration(
-                    Identifier::token_to_id(name),
-                    None,
-                ))
-            }
-            (None, _) => return Err(ParseError::MissingVariableName(self.previous())),
-        }
+        )?;
+        Ok(Statement::VariableDeclaration(
+            Identifier::token_to_id(name),
+            init,
+        ))
     }
 
     fn fun_declaration(&mut self, kind: &str) -> ParseResult<Statement> {
-        let fun_name = self.consume(TokenType::IDENTIFIER, format!("Expect {} name.", kind));
+        let fun_name = self.consume(TokenType::IDENTIFIER, format!("Expect {} name.", kind))?;
 
         self.consume(
             TokenType::LEFTPAREN,
             format!("Expect '(' after {} name.", kind),
-        );
+        )?;
         let mut parameters: Vec<Identifier> = vec![];
 
         if !self.check_token_type(TokenType::RIGHTPAREN) {
-            self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))
-                .and_then(|var_name| Some(parameters.push(Identifier::token_to_id(var_name))));
+            let name =
+                self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))?;
+            parameters.push(Identifier::token_to_id(name));
 
             while let Some(_) = self.match_operator_type(vec![CallOperator::COMMA]) {
                 if parameters.len() >= 255 {
                     return Err(ParseError::CallArgumentSize(self.previous()));
                 }
-                self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))
-                    .and_then(|var_name| Some(parameters.push(Identifier::token_to_id(var_name))));
+                let name =
+                    self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))?;
+                parameters.push(Identifier::token_to_id(name));
             }
         };
 
         self.consume(
             TokenType::RIGHTPAREN,
             String::from("Expect ')' after parameters"),
-        );
+        )?;
         self.consume(
             TokenType::LEFTBRACE,
             format!("Expect '{{' before {} body.", kind),
-        );
+        )?;
 
-        let body = self.block();
+        let body = self.block()?;
         return Ok(Statement::FunctionDeclaration(FunctionDeclaration::new(
-            Identifier::token_to_id(fun_name.unwrap()),
+            Identifier::token_to_id(fun_name),
             parameters,
             body,
+            self.source.clone(),
         )));
     }
 
     fn class_declaration(&mut self) -> ParseResult<Statement> {
-        let class_name = self.consume(TokenType::IDENTIFIER, ("Expect class name.").to_string());
+        let class_name = self.consume(TokenType::IDENTIFIER, ("Expect class name.").to_string())?;
+
+        let mut superclass = None;
+        if self.peek().typ == TokenType::LESS {
+            self.advance();
+            let superclass_name =
+                self.consume(TokenType::IDENTIFIER, "Expect superclass name.".to_string())?;
+            superclass = Some(Variable::default(Identifier::token_to_id(superclass_name)));
+        }
+
         self.consume(
             TokenType::LEFTBRACE,
             "Expect '{' before class body.".to_string(),
-        );
+        )?;
 
         let mut methods: Vec<Box<Statement>> = vec![];
         while self.peek().typ != TokenType::RIGHTBRACE && !self.is_at_end() {
@@ -159,14 +196,37 @@ impl Parser {
         self.consume(
             TokenType::RIGHTBRACE,
             "Expect '}' after class body.".to_string(),
-        );
+        )?;
 
         Ok(Statement::ClassDeclaration(
-            Identifier::token_to_id(class_name.unwrap()),
+            Identifier::token_to_id(class_name),
+            superclass,
             methods,
         ))
     }
 
+    /// `import "path";` or `import name from "path";`. `from` is not a
+    /// keyword, so that it stays usable as a name.
+    fn import_declaration(&mut self, keyword: Token) -> ParseResult<Statement> {
+        let mut name = None;
+        if self.check_token_type(TokenType::IDENTIFIER) {
+            name = Some(Identifier::token_to_id(self.advance()));
+            if self.peek().typ != TokenType::IDENTIFIER || self.peek().lexeme != "from" {
+                return Err(ParseError::Expected(
+                    self.peek(),
+                    String::from("Expect 'from' after imported name."),
+                ));
+            }
+            self.advance();
+        }
+        let path = self.consume(TokenType::STRING, String::from("Expect module path."))?;
+        self.consume(
+            TokenType::SEMICOLON,
+            String::from("Expect ';' after import."),
+        )?;
+        Ok(Statement::ImportStatement(keyword, path, name))
+    }
+
     fn statement(&mut self) -> ParseResult<Statement> {
         match &self.peek().typ {
             TokenType::IF => {
@@ -189,21 +249,50 @@ impl Parser {
                 self.advance();
                 return self.return_statement();
             }
+            TokenType::BREAK => {
+                let keyword = self.advance();
+                self.consume(
+                    TokenType::SEMICOLON,
+                    String::from("Expect ';' after 'break'."),
+                )?;
+                return Ok(Statement::BreakStatement(keyword));
+            }
+            TokenType::CONTINUE => {
+                let keyword = self.advance();
+                self.consume(
+                    TokenType::SEMICOLON,
+                    String::from("Expect ';' after 'continue'."),
+                )?;
+                return Ok(Statement::ContinueStatement(keyword));
+            }
             TokenType::LEFTBRACE => {
                 self.advance();
-                return Ok(Statement::BlockStatement(self.block()));
+                return Ok(Statement::BlockStatement(self.block()?));
+            }
+            TokenType::THROW => {
+                let keyword = self.advance();
+                let value = self.expression()?;
+                self.consume(
+                    TokenType::SEMICOLON,
+                    String::from("Expect ';' after thrown value."),
+                )?;
+                return Ok(Statement::ThrowStatement(keyword, Box::new(value)));
+            }
+            TokenType::TRY => {
+                self.advance();
+                return self.try_statement();
             }
             _ => self.expression_statement(),
         }
     }
 
     fn if_statement(&mut self) -> ParseResult<Statement> {
-        self.consume(TokenType::LEFTPAREN, String::from("Expect '(' after if."));
+        self.consume(TokenType::LEFTPAREN, String::from("Expect '(' after if."))?;
         let condition = self.expression()?;
         self.consume(
             TokenType::RIGHTPAREN,
             String::from("Expect ')' after if condition."),
-        );
+        )?;
 
         let then_branch = self.statement()?;
         let mut else_branch = None;
@@ -221,47 +310,94 @@ impl Parser {
         ))
     }
 
+    fn try_statement(&mut self) -> ParseResult<Statement> {
+        let keyword = self.previous();
+        self.consume(
+            TokenType::LEFTBRACE,
+            String::from("Expect '{' after 'try'."),
+        )?;
+        let body = self.block()?;
+
+        let mut catch = None;
+        if self.check_token_type(TokenType::CATCH) {
+            self.advance();
+            self.consume(
+                TokenType::LEFTPAREN,
+                String::from("Expect '(' after 'catch'."),
+            )?;
+            let name = self.consume(
+                TokenType::IDENTIFIER,
+                String::from("Expect variable name after '('."),
+            )?;
+            self.consume(
+                TokenType::RIGHTPAREN,
+                String::from("Expect ')' after catch variable."),
+            )?;
+            self.consume(
+                TokenType::LEFTBRACE,
+                String::from("Expect '{' before catch body."),
+            )?;
+            catch = Some((Identifier::token_to_id(name), self.block()?));
+        }
+
+        let mut finally = None;
+        if self.check_token_type(TokenType::FINALLY) {
+            self.advance();
+            self.consume(
+                TokenType::LEFTBRACE,
+                String::from("Expect '{' after 'finally'."),
+            )?;
+            finally = Some(self.block()?);
+        }
+
+        if catch.is_none() && finally.is_none() {
+            return Err(ParseError::Expected(
+                self.peek(),
+                String::from("Expect 'catch' or 'finally' after try block."),
+            ));
+        }
+        Ok(Statement::TryStatement(keyword, body, catch, finally))
+    }
+
     fn print_statement(&mut self) -> ParseResult<Statement> {
         let expr = self.expression()?;
         self.consume(
             TokenType::SEMICOLON,
             String::from("Expect ';' after value."),
-        );
+        )?;
         Ok(Statement::PrintStatement(Box::new(expr)))
     }
 
     fn return_statement(&mut self) -> ParseResult<Statement> {
+        let keyword = self.previous();
         let expr = match self.peek().typ {
-            TokenType::SEMICOLON => Some(ExpressionNode::new(
-                self.current_line(),
-                Expression::Literal(Literal::Nil),
-            )),
-            _ => Some(self.expression()?),
-        }
-        .unwrap();
+            TokenType::SEMICOLON => None,
+            _ => Some(Box::new(self.expression()?)),
+        };
 
         self.consume(
             TokenType::SEMICOLON,
             String::from("Expect ';' after return value."),
-        );
+        )?;
 
-        Ok(Statement::ReturnStatement(Some(Box::new(expr))))
+        Ok(Statement::ReturnStatement(keyword, expr))
     }
 
     fn while_statement(&mut self) -> ParseResult<Statement> {
         self.consume(
             TokenType::LEFTPAREN,
             String::from("Expect '(' after 'while'."),
-        );
+        )?;
         let condition = self.expression()?;
         self.consume(
             TokenType::RIGHTPAREN,
             String::from("Expect ')' after while condition."),
-        );
+        )?;
         let body = self.statement()?;
         Ok(Statement::WhileStatement(
             Box::new(condition),
             Box::new(body),
+            None,
         ))
     }
 
@@ -269,48 +405,47 @@ impl Parser {
         self.consume(
             TokenType::LEFTPAREN,
             String::from("Expect '(' after 'for'."),
-        );
+        )?;
 
-        let initializer = match self.advance().typ {
-            TokenType::SEMICOLON => None,
-            TokenType::VAR => Some(self.var_declaration()?),
+        let initializer = match self.peek().typ {
+            TokenType::SEMICOLON => {
+                self.advance();
+                None
+            }
+            TokenType::VAR => {
+                self.advance();
+                Some(self.var_declaration()?)
+            }
             _ => Some(self.expression_statement()?),
         };
 
         let condition = match self.peek().typ {
             TokenType::SEMICOLON => ExpressionNode::new(
-                self.current_line(),
+                self.peek().span,
                 Expression::Literal(Literal::Boolean(true)),
             ),
             _ => self.expression()?,
         };
+        self.consume(
+            TokenType::SEMICOLON,
+            String::from("Expect ';' after loop condition."),
+        )?;
 
-        // We evaluate increment first to make the code simpler
-        let increment = match self.advance().typ {
+        let increment = match self.peek().typ {
             TokenType::RIGHTPAREN => None,
-            _ => Some(self.expression()?),
+            _ => Some(Box::new(self.expression()?)),
         };
 
         self.consume(
             TokenType::RIGHTPAREN,
             String::from("Expect ')' after 'for'."),
-        );
-
-        let mut body = self.statement()?;
-
-        // If increment is a valid expression, we replace the current body with
-        // a block statement that holds the current body statements and adds an additional
-        // expression that evaluates the increment expression
-        increment.and_then(|increment| {
-            body = Statement::BlockStatement(vec![
-                body.clone(),
-                Statement::ExpressionStatement(Box::new(increment)),
-            ]);
-            Some(())
-        });
+        )?;
+
+        let body = self.statement()?;
 
-        // We build a loop with a primitive while loop
-        body = Statement::WhileStatement(Box::new(condition), Box::new(body.clone()));
+        // We build a loop with a primitive while loop. The increment is kept apart
+        // from the body so that it still runs when the body hits a `continue`.
+        let mut body = Statement::WhileStatement(Box::new(condition), Box::new(body), increment);
 
         initializer.and_then(|initializer| {
             body = Statement::BlockStatement(vec![initializer, body.clone()]);
@@ -325,22 +460,24 @@ impl Parser {
         self.consume(
             TokenType::SEMICOLON,
             String::from("Expect ';' after expression."),
-        );
+        )?;
         Ok(Statement::ExpressionStatement(Box::new(expr)))
     }
 
-    fn block(&mut self) -> Vec<Statement> {
+    fn block(&mut self) -> ParseResult<Vec<Statement>> {
         let mut statements: Vec<Statement> = vec![];
+        self.block_depth += 1;
         while self.peek().typ != TokenType::RIGHTBRACE && !self.is_at_end() {
-            let _ = self
-                .declaration()
-                .map(|statement| statements.push(statement));
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
         }
+        self.block_depth -= 1;
         self.consume(
             TokenType::RIGHTBRACE,
             String::from("Expect '}' after block."),
-        );
-        statements
+        )?;
+        Ok(statements)
     }
 
     fn expression(&mut self) -> ParseResult<ExpressionNode> {
@@ -354,7 +491,7 @@ impl Parser {
             let right_expr = self.expression()?;
 
             return Ok(ExpressionNode::new(
-                self.current_line(),
+                expr.span().to(right_expr.span()),
                 Expression::BinaryExpression(Box::new(expr), binary_op, Box::new(right_expr)),
             ));
         }
@@ -371,13 +508,13 @@ impl Parser {
             match expr.expr() {
                 Expression::Variable(name) => {
                     return Ok(ExpressionNode::new(
-                        self.current_line(),
+                        expr.span().to(value_expr.span()),
                         Expression::Assignment(name.clone(), Box::new(value_expr)),
                     ))
                 }
                 Expression::GetExpression(name, get_expr) => {
                     return Ok(ExpressionNode::new(
-                        self.current_line(),
+                        expr.span().to(value_expr.span()),
                         Expression::SetExpression(
                             get_expr.clone(),
                             name.clone(),
@@ -385,7 +522,18 @@ impl Parser {
                         ),
                     ))
                 }
-                _ => return Err(ParseError::MissingExpr(token)),
+                Expression::IndexGetExpression(object, bracket, index) => {
+                    return Ok(ExpressionNode::new(
+                        expr.span().to(value_expr.span()),
+                        Expression::IndexSetExpression(
+                            object.clone(),
+                            bracket.clone(),
+                            index.clone(),
+                            Box::new(value_expr),
+                        ),
+                    ))
+                }
+                _ => return Err(ParseError::InvalidAssignmentTarget(token)),
             }
         }
         Ok(expr)
@@ -397,7 +545,7 @@ impl Parser {
         while let Some(logical_op) = self.match_operator_type(vec![LogicalOperator::OR]) {
             let right_expr = self.and()?;
             left_expr = ExpressionNode::new(
-                self.current_line(),
+                left_expr.span().to(right_expr.span()),
                 Expression::Or(Box::new(left_expr), logical_op, Box::new(right_expr)),
             );
         }
@@ -410,7 +558,7 @@ impl Parser {
         while let Some(logical_op) = self.match_operator_type(vec![LogicalOperator::AND]) {
             let right_expr = self.ternary()?;
             left_expr = ExpressionNode::new(
-                self.current_line(),
+                left_expr.span().to(right_expr.span()),
                 Expression::And(Box::new(left_expr), logical_op, Box::new(right_expr)),
             );
         }
@@ -426,11 +574,11 @@ impl Parser {
             self.consume(
                 TokenType::COLON,
                 String::from("Expect ':' after then branch of ternary expression."),
-            );
+            )?;
 
             let right_expr = self.expression()?;
             expr = ExpressionNode::new(
-                self.current_line(),
+                expr.span().to(right_expr.span()),
                 Expression::TernaryExpression(
                     Box::new(expr),
                     ternary_op,
@@ -450,7 +598,7 @@ impl Parser {
         {
             let right_expr = self.comparison()?;
             expr = ExpressionNode::new(
-                self.current_line(),
+                expr.span().to(right_expr.span()),
                 Expression::BinaryExpression(Box::new(expr), binary_op, Box::new(right_expr)),
             );
         }
@@ -468,7 +616,7 @@ impl Parser {
         ]) {
             let right_expr = self.term()?;
             expr = ExpressionNode::new(
-                self.current_line(),
+                expr.span().to(right_expr.span()),
                 Expression::BinaryExpression(Box::new(expr), binary_op, Box::new(right_expr)),
             );
         }
@@ -483,7 +631,7 @@ impl Parser {
         {
             let right_expr = self.factor()?;
             expr = ExpressionNode::new(
-                self.current_line(),
+                expr.span().to(right_expr.span()),
                 Expression::BinaryExpression(Box::new(expr), binary_op, Box::new(right_expr)),
             );
         }
@@ -498,7 +646,7 @@ impl Parser {
         {
             let right_expr = self.unary()?;
             expr = ExpressionNode::new(
-                self.current_line(),
+                expr.span().to(right_expr.span()),
                 Expression::BinaryExpression(Box::new(expr), binary_op, Box::new(right_expr)),
             );
         }
@@ -509,9 +657,10 @@ impl Parser {
         while let Some(unary_op) =
             self.match_operator_type(vec![UnaryOperator::BANG, UnaryOperator::MINUS])
         {
+            let operator = self.previous();
             let right_expr = self.unary()?;
             return Ok(ExpressionNode::new(
-                self.current_line(),
+                operator.span.to(right_expr.span()),
                 Expression::Unary(unary_op, Box::new(right_expr)),
             ));
         }
@@ -525,17 +674,18 @@ impl Parser {
                 self.advance();
                 expr = self.finish_call(expr)?;
             } else if self.peek().typ == TokenType::DOT {
+                self.advance();
                 let name = self.consume(
                     TokenType::IDENTIFIER,
                     "Expect property name after '.'.".to_string(),
-                );
+                )?;
                 expr = ExpressionNode::new(
-                    self.current_line(),
-                    Expression::GetExpression(
-                        Identifier::token_to_id(name.unwrap()),
-                        Box::new(expr),
-                    ),
+                    expr.span().to(name.span),
+                    Expression::GetExpression(Identifier::token_to_id(name), Box::new(expr)),
                 )
+            } else if self.peek().typ == TokenType::LEFTBRACKET {
+                self.advance();
+                expr = self.finish_index(expr)?;
             } else {
                 break;
             }
@@ -543,6 +693,41 @@ impl Parser {
         Ok(expr)
     }
 
+    fn finish_index(&mut self, object: ExpressionNode) -> ParseResult<ExpressionNode> {
+        let start = match self.peek().typ {
+            TokenType::COLON => None,
+            _ => Some(Box::new(self.expression()?)),
+        };
+
+        if self.peek().typ == TokenType::COLON {
+            self.advance();
+            let end = match self.peek().typ {
+                TokenType::RIGHTBRACKET => None,
+                _ => Some(Box::new(self.expression()?)),
+            };
+            let bracket = self.consume(
+                TokenType::RIGHTBRACKET,
+                String::from("Expect ']' after slice."),
+            )?;
+            return Ok(ExpressionNode::new(
+                object.span().to(bracket.span),
+                Expression::SliceExpression(Box::new(object), bracket, start, end),
+            ));
+        }
+
+        let bracket = self.consume(
+            TokenType::RIGHTBRACKET,
+            String::from("Expect ']' after index."),
+        )?;
+        match start {
+            Some(index) => Ok(ExpressionNode::new(
+                object.span().to(bracket.span),
+                Expression::IndexGetExpression(Box::new(object), bracket, index),
+            )),
+            None => Err(ParseError::MissingExpr(bracket)),
+        }
+    }
+
     fn finish_call(&mut self, callee: ExpressionNode) -> ParseResult<ExpressionNode> {
         let mut args: Vec<ExpressionNode> = vec![];
 
@@ -562,50 +747,106 @@ impl Parser {
             }
         };
 
-        let token = self
-            .consume(
-                TokenType::RIGHTPAREN,
-                String::from("Expect ')' after expression."),
-            )
-            .unwrap();
+        let token = self.consume(
+            TokenType::RIGHTPAREN,
+            String::from("Expect ')' after expression."),
+        )?;
 
         Ok(ExpressionNode::new(
-            self.current_line(),
+            callee.span().to(token.span),
             Expression::CallExpression(Box::new(callee), token, args),
         ))
     }
 
+    /// Parses a string with interpolated expressions into the concatenation
+    /// of its parts. It starts with the text before the first expression,
+    /// even if empty, so that `+` converts numbers to strings. Every `+`
+    /// spans the part it appends, which runtime errors point at.
+    fn interpolation(&mut self) -> ParseResult<ExpressionNode> {
+        let start = self.peek().span;
+        let mut parts: Option<ExpressionNode> = None;
+        loop {
+            if !matches!(
+                self.peek().typ,
+                TokenType::INTERPOLATION | TokenType::STRING
+            ) {
+                return Err(ParseError::Expected(
+                    self.peek(),
+                    String::from("Expect end of string."),
+                ));
+            }
+            let text = self.advance();
+            let done = text.typ == TokenType::STRING;
+            let is_empty = matches!(&text.literal, Some(Literal::String(text)) if text.is_empty());
+            if parts.is_none() || !is_empty {
+                let literal = text.literal.clone().unwrap_or(Literal::Nil);
+                let literal = ExpressionNode::new(text.span, Expression::Literal(literal));
+                parts = Some(Self::append(parts, literal));
+            }
+            if done {
+                break;
+            }
+            let value = self.expression()?;
+            parts = Some(Self::append(parts, value));
+            self.consume(
+                TokenType::RIGHTBRACE,
+                String::from("Expect '}' after interpolated expression."),
+            )?;
+        }
+        let parts = parts.ok_or(ParseError::MissingExpr(self.previous()))?;
+        Ok(ExpressionNode::new(
+            start.to(self.previous().span),
+            Expression::Grouping(Box::new(parts)),
+        ))
+    }
+
+    fn append(parts: Option<ExpressionNode>, part: ExpressionNode) -> ExpressionNode {
+        match parts {
+            Some(parts) => ExpressionNode::new(
+                part.span(),
+                Expression::BinaryExpression(Box::new(parts), BinaryOperator::PLUS, Box::new(part)),
+            ),
+            None => part,
+        }
+    }
+
     fn primary(&mut self) -> ParseResult<ExpressionNode> {
         let current_token = self.peek();
-        let current_line = self.current_line();
+        let current_span = current_token.span;
 
         match current_token.typ {
             TokenType::TRUE => {
                 self.advance();
                 return Ok(ExpressionNode::new(
-                    current_line,
+                    current_span,
                     Expression::Literal(Literal::Boolean(true)),
                 ));
             }
             TokenType::FALSE => {
                 self.advance();
                 return Ok(ExpressionNode::new(
-                    current_line,
+                    current_span,
                     Expression::Literal(Literal::Boolean(false)),
                 ));
             }
             TokenType::NIL => {
                 self.advance();
                 return Ok(ExpressionNode::new(
-                    current_line,
+                    current_span,
                     Expression::Literal(Literal::Nil),
                 ));
             }
+            TokenType::INTERPOLATION => return self.interpolation(),
             TokenType::NUMBER | TokenType::STRING => {
                 self.advance();
                 return Ok(ExpressionNode::new(
-                    current_line,
-                    Expression::Literal(current_token.literal.unwrap()),
+                    current_span,
+                    Expression::Literal(
+                        current_token
+                            .literal
+                            .clone()
+                            .ok_or(ParseError::MissingExpr(current_token))?,
+                    ),
                 ));
             }
             TokenType::LEFTPAREN => {
@@ -614,19 +855,88 @@ impl Parser {
                 self.consume(
                     TokenType::RIGHTPAREN,
                     String::from("Expect ')' after expression."),
-                );
+                )?;
                 return Ok(ExpressionNode::new(
-                    current_line,
+                    current_span.to(self.previous().span),
                     Expression::Grouping(Box::new(expr)),
                 ));
             }
             TokenType::IDENTIFIER => {
                 self.advance();
                 return Ok(ExpressionNode::new(
-                    current_line,
+                    current_span,
                     Expression::Variable(Variable::default(Identifier::token_to_id(current_token))),
                 ));
             }
+            TokenType::SUPER => {
+                self.advance();
+                self.consume(TokenType::DOT, "Expect '.' after 'super'.".to_string())?;
+                let method = self.consume(
+                    TokenType::IDENTIFIER,
+                    "Expect superclass method name.".to_string(),
+                )?;
+                return Ok(ExpressionNode::new(
+                    current_span.to(method.span),
+                    Expression::Super(
+                        Variable::default(Identifier::token_to_id(current_token)),
+                        Identifier::token_to_id(method),
+                    ),
+                ));
+            }
+            TokenType::THIS => {
+                self.advance();
+                return Ok(ExpressionNode::new(
+                    current_span,
+                    Expression::This(Variable::default(Identifier::token_to_id(current_token))),
+                ));
+            }
+            TokenType::LEFTBRACKET => {
+                self.advance();
+                let mut elements: Vec<ExpressionNode> = vec![];
+                while !self.check_token_type(TokenType::RIGHTBRACKET) && !self.is_at_end() {
+                    elements.push(self.assignment()?);
+                    if self
+                        .match_operator_type(vec![CallOperator::COMMA])
+                        .is_none()
+                    {
+                        break;
+                    }
+                }
+                self.consume(
+                    TokenType::RIGHTBRACKET,
+                    String::from("Expect ']' after list elements."),
+                )?;
+                return Ok(ExpressionNode::new(
+                    current_span.to(self.previous().span),
+                    Expression::ListExpression(elements),
+                ));
+            }
+            TokenType::LEFTBRACE => {
+                // Blocks are parsed as statements, so a brace in expression
+                // position always starts a map literal.
+                self.advance();
+                let mut entries: Vec<(ExpressionNode, ExpressionNode)> = vec![];
+                while !self.check_token_type(TokenType::RIGHTBRACE) && !self.is_at_end() {
+                    let key = self.assignment()?;
+                    self.consume(TokenType::COLON, String::from("Expect ':' after map key."))?;
+                    let value = self.assignment()?;
+                    entries.push((key, value));
+                    if self
+                        .match_operator_type(vec![CallOperator::COMMA])
+                        .is_none()
+                    {
+                        break;
+                    }
+                }
+                self.consume(
+                    TokenType::RIGHTBRACE,
+                    String::from("Expect '}' after map entries."),
+                )?;
+                return Ok(ExpressionNode::new(
+                    current_span.to(self.previous().span),
+                    Expression::MapExpression(entries),
+                ));
+            }
             TokenType::FUN => {
                 self.advance();
                 return self.fun_expression("function");
@@ -638,39 +948,46 @@ impl Parser {
     }
 
     fn fun_expression(&mut self, kind: &str) -> ParseResult<ExpressionNode> {
+        let keyword = self.previous();
         self.consume(
             TokenType::LEFTPAREN,
             format!("Expect '(' after {} name.", kind),
-        );
+        )?;
         let mut parameters: Vec<Identifier> = vec![];
 
         if !self.check_token_type(TokenType::RIGHTPAREN) {
-            self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))
-                .and_then(|var_name| Some(parameters.push(Identifier::token_to_id(var_name))));
+            let name =
+                self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))?;
+            parameters.push(Identifier::token_to_id(name));
 
             while let Some(_) = self.match_operator_type(vec![CallOperator::COMMA]) {
                 if parameters.len() >= 255 {
                     return Err(ParseError::CallArgumentSize(self.previous()));
                 }
-                self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))
-                    .and_then(|var_name| Some(parameters.push(Identifier::token_to_id(var_name))));
+                let name =
+                    self.consume(TokenType::IDENTIFIER, String::from("Expect variable name."))?;
+                parameters.push(Identifier::token_to_id(name));
             }
         };
 
         self.consume(
             TokenType::RIGHTPAREN,
             String::from("Expect ')' after parameters"),
-        );
+        )?;
         self.consume(
             TokenType::LEFTBRACE,
             format!("Expect '{{' before {} body.", kind),
-        );
+        )?;
 
-        let body = self.block();
+        let body = self.block()?;
 
         Ok(ExpressionNode::new(
-            self.current_line(),
-            Expression::FunctionExpression(FunctionExpression::new(parameters, body)),
+            keyword.span.to(self.previous().span),
+            Expression::FunctionExpression(FunctionExpression::new(
+                parameters,
+                body,
+                self.source.clone(),
+            )),
         ))
     }
 
@@ -701,15 +1018,11 @@ impl Parser {
 
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
-            self.current_token = self.current_token + 1;
+            self.current_token += 1;
         }
         self.previous()
     }
 
-    fn current_line(&mut self) -> usize {
-        self.peek().line
-    }
-
     fn previous(&self) -> Token {
         if self.current_token == 0 {
             return self.tokens.get(self.current_token).unwrap().clone();
@@ -718,25 +1031,23 @@ impl Parser {
         }
     }
 
-    fn consume(&mut self, typ: TokenType, message: String) -> Option<Token> {
+    fn consume(&mut self, typ: TokenType, message: String) -> ParseResult<Token> {
         if self.check_token_type(typ) {
-            return Some(self.advance());
+            return Ok(self.advance());
         }
-        self.error(self.peek(), message);
-        None
-    }
-
-    fn error(&self, token: Token, message: String) {
-        Error::error(token.line, message);
+        Err(ParseError::Expected(self.peek(), message))
     }
 
+    /// Skips tokens until the start of the next statement: just past a `;`, or
+    /// before a keyword that begins a statement or the `}` closing a block.
     fn synchronize(&mut self) {
-        self.advance();
         while !self.is_at_end() {
-            if self.previous().typ == TokenType::SEMICOLON {
-                break;
-            }
             match self.peek().typ {
+                TokenType::SEMICOLON => {
+                    self.advance();
+                    return;
+                }
+                TokenType::RIGHTBRACE if self.block_depth > 0 => return,
                 TokenType::CLASS
                 | TokenType::FUN
                 | TokenType::VAR
@@ -744,10 +1055,16 @@ impl Parser {
                 | TokenType::IF
                 | TokenType::WHILE
                 | TokenType::PRINT
-                | TokenType::RETURN => break,
-                _ => {}
+                | TokenType::RETURN
+                | TokenType::BREAK
+                | TokenType::CONTINUE
+                | TokenType::IMPORT
+                | TokenType::THROW
+                | TokenType::TRY => return,
+                _ => {
+                    self.advance();
+                }
             }
-            self.advance();
         }
     }
 }
@@ -1,14 +1,34 @@
-use super::{ExpressionNode, FunctionDeclaration, Identifier};
+use super::{ExpressionNode, FunctionDeclaration, Identifier, Token, Variable};
 
 #[derive(Debug, Clone)]
 pub enum Statement {
     PrintStatement(Box<ExpressionNode>),
     IfStatement(Box<ExpressionNode>, Box<Statement>, Option<Box<Statement>>),
-    WhileStatement(Box<ExpressionNode>, Box<Statement>),
+    WhileStatement(
+        Box<ExpressionNode>,
+        Box<Statement>,
+        Option<Box<ExpressionNode>>,
+    ),
     ExpressionStatement(Box<ExpressionNode>),
-    ClassDeclaration(Identifier, Vec<Box<Statement>>),
+    ClassDeclaration(Identifier, Option<Variable>, Vec<Box<Statement>>),
     VariableDeclaration(Identifier, Option<Box<ExpressionNode>>),
     FunctionDeclaration(FunctionDeclaration),
     BlockStatement(Vec<Statement>),
-    ReturnStatement(Option<Box<ExpressionNode>>),
+    ReturnStatement(Token, Option<Box<ExpressionNode>>),
+    BreakStatement(Token),
+    ContinueStatement(Token),
+    /// The `import` keyword, the string naming the file and the variable to
+    /// import from it, if only one is.
+    ImportStatement(Token, Token, Option<Identifier>),
+    /// The `throw` keyword and the value thrown.
+    ThrowStatement(Token, Box<ExpressionNode>),
+    /// The `try` keyword, the statements of its block, the variable and
+    /// statements of the `catch` clause and the statements of the `finally`
+    /// block. At least one of the clauses is present.
+    TryStatement(
+        Token,
+        Vec<Statement>,
+        Option<(Identifier, Vec<Statement>)>,
+        Option<Vec<Statement>>,
+    ),
 }
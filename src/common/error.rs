@@ -1,17 +1,128 @@
-pub use super::{Token, TokenType};
+pub use super::{Sources, Span, Token, TokenType};
+use crate::sandbox::Limit;
 
+/// An error found before the program runs, by the scanner, parser or resolver.
+/// `location` names the offending token for parse errors, like `at 'x'`.
 #[derive(Debug)]
 pub struct Error {
     pub message: String,
-    pub line: usize,
+    pub location: String,
+    pub span: Span,
 }
 
 impl Error {
-    pub fn error(line: usize, message: String) {
-        Error::report(line, String::from(""), message);
+    pub fn new(span: Span, message: String) -> Self {
+        Self {
+            message,
+            location: String::new(),
+            span,
+        }
+    }
+
+    pub fn at(span: Span, location: String, message: String) -> Self {
+        Self {
+            message,
+            location,
+            span,
+        }
+    }
+
+    /// Formats the error along with the line it points at.
+    pub fn render(&self, sources: &Sources) -> String {
+        render(self.span, &self.location, &self.message, sources)
+    }
+}
+
+fn render(span: Span, location: &str, message: &str, sources: &Sources) -> String {
+    let mut text = format!("[line {} ] Error {} : {}", span.line, location, message);
+    let snippet = sources.render(span);
+    if !snippet.is_empty() {
+        text.push('\n');
+        text.push_str(&snippet);
+    }
+    text
+}
+
+/// An error raised while executing a program. It unwinds through blocks,
+/// loops and function calls until a `try` statement catches it, or else
+/// reaches the driver, which aborts the script.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: usize,
+    pub span: Span,
+    pub trace: Vec<StackFrame>,
+    /// Set when a `throw` statement raised the error. The backend keeps the
+    /// thrown value until a `catch` clause receives it; `message` describes it
+    /// in case none does.
+    pub thrown: bool,
+    /// Set when the program ran out of a limit of its sandbox. Such errors
+    /// can't be caught.
+    pub limit: Option<Limit>,
+}
+
+/// One entry of the Lox call stack at the point a runtime error was raised.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub function: String,
+    pub line: usize,
+}
+
+impl RuntimeError {
+    pub fn new(span: Span, message: String) -> Self {
+        Self {
+            message,
+            line: span.line,
+            span,
+            trace: vec![],
+            thrown: false,
+            limit: None,
+        }
     }
 
-    pub fn report(line: usize, location: String, message: String) {
-        println!("[line {} ] Error {} : {}", line, location, message)
+    /// Formats the error, the line it points at and the stack trace.
+    /// Runs of calls from the same line of a function, as in runaway
+    /// recursion, are shown once with a count, and only the innermost and
+    /// outermost frames of a trace that is still long are shown.
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut text = render(self.span, "at runtime", &self.message, sources);
+        let mut runs: Vec<(&StackFrame, usize)> = vec![];
+        for frame in &self.trace {
+            match runs.last_mut() {
+                Some((last, count))
+                    if last.function == frame.function && last.line == frame.line =>
+                {
+                    *count += 1
+                }
+                _ => runs.push((frame, 1)),
+            }
+        }
+        let hidden = runs.len().saturating_sub(2 * TRACE_EDGE);
+        for (i, (frame, count)) in runs.iter().enumerate() {
+            if hidden > 0 && i == TRACE_EDGE {
+                text.push_str(&format!("\n    ... {} more frames", hidden));
+            }
+            if hidden > 0 && (TRACE_EDGE..TRACE_EDGE + hidden).contains(&i) {
+                continue;
+            }
+            text.push_str(&format!(
+                "\n    at {} (line {})",
+                frame.function, frame.line
+            ));
+            if *count > 1 {
+                let calls = if *count == 2 { "call" } else { "calls" };
+                text.push_str(&format!(
+                    "\n    ... {} more {} to {}",
+                    count - 1,
+                    calls,
+                    frame.function
+                ));
+            }
+        }
+        text
     }
 }
+
+/// How many frames are shown at either end of a stack trace too long to show
+/// whole.
+const TRACE_EDGE: usize = 10;
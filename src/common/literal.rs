@@ -1,6 +1,8 @@
-use super::{LoxCallable, LoxClass, LoxInstance};
+use super::{LoxCallable, LoxClass, LoxInstance, LoxMap};
+use crate::gc::Tracer;
+use std::cell::RefCell;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::string::String;
 
 #[derive(Debug, Clone)]
@@ -11,6 +13,8 @@ pub enum Literal {
     Callable(Rc<dyn LoxCallable>),
     Class(Rc<LoxClass>),
     Instance(Rc<LoxInstance>),
+    List(Rc<RefCell<Vec<Literal>>>),
+    Map(Rc<RefCell<LoxMap>>),
     Nil,
 }
 
@@ -25,6 +29,26 @@ impl Display for Literal {
             Literal::Callable(val) => write!(f, "{}", val),
             Literal::Class(class) => write!(f, "class {}", class.to_string()),
             Literal::Instance(instance) => write!(f, "instance {}", instance.to_string()),
+            Literal::List(list) => {
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Literal::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key.to_literal(), value)?;
+                }
+                write!(f, "}}")
+            }
             Literal::Nil => write!(f, "NIL"),
         }
     }
@@ -38,6 +62,8 @@ impl PartialEq for Literal {
             (&Boolean(a), &Boolean(b)) => a == b,
             (&Nil, &Nil) => true,
             (&Callable(ref a), &Callable(ref b)) => Rc::ptr_eq(a, b),
+            (&List(ref a), &List(ref b)) => Rc::ptr_eq(a, b),
+            (&Map(ref a), &Map(ref b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
@@ -61,15 +87,41 @@ impl Literal {
                 }
             }
             Literal::Boolean(b) => *b,
+            Literal::List(list) => !list.borrow().is_empty(),
+            Literal::Map(map) => !map.borrow().is_empty(),
             Literal::Nil | _ => false,
         }
     }
 
+    /// Reports the object this value refers to, if any, to the collector.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Literal::Callable(callable) => {
+                let object: Weak<dyn LoxCallable> = Rc::downgrade(callable);
+                tracer.edge_weak(object, Rc::strong_count(callable));
+            }
+            Literal::Class(class) => tracer.edge(class),
+            Literal::Instance(instance) => tracer.edge(instance),
+            Literal::List(list) => tracer.edge(list),
+            Literal::Map(map) => tracer.edge(map),
+            Literal::String(_) | Literal::Number(_) | Literal::Boolean(_) | Literal::Nil => {}
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Literal::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
     pub fn into_callable(self) -> Option<Rc<dyn LoxCallable>> {
         match self {
             Literal::Callable(f) => Some(f),
             Literal::Class(c) => Some(c),
             Literal::Instance(_)
+            | Literal::List(_)
+            | Literal::Map(_)
             | Literal::String(_)
             | Literal::Number(_)
             | Literal::Boolean(_)
@@ -1,10 +1,15 @@
 pub mod error;
 pub mod literal;
+pub mod source;
+pub mod span;
 
 pub use super::ast::UnaryOperator;
-pub use super::runtime::{LoxCallable, LoxClass, LoxInstance};
+pub use super::runtime::{LoxCallable, LoxClass, LoxInstance, LoxMap};
 pub use super::scanner::{Token, TokenType};
 pub use literal::Literal;
+pub use source::Sources;
+pub use span::Span;
 
-pub use error::Error;
+pub use error::{Error, RuntimeError, StackFrame};
 pub type Result<T> = ::std::result::Result<T, Error>;
+pub type RuntimeResult<T> = ::std::result::Result<T, RuntimeError>;
@@ -0,0 +1,64 @@
+use super::Span;
+use std::rc::{Rc, Weak};
+
+/// The texts of the programs and modules run so far, which spans point into.
+/// Each text gets a range of offsets of its own, so the offset of a span says
+/// which text it is in. A text is only kept while something holds on to it:
+/// the run of the program, the module cache or a function declared in it.
+#[derive(Debug, Default)]
+pub struct Sources {
+    /// The texts in order of the offsets they start at.
+    texts: Vec<Text>,
+    /// The offset the next text starts at.
+    end: usize,
+}
+
+#[derive(Debug)]
+struct Text {
+    offset: usize,
+    text: Weak<str>,
+    /// The file the text was read from, which errors in it name.
+    file: Option<String>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `text`, read from `file` if it came from one, and returns it
+    /// along with the offset its spans start at. Texts nobody holds any more
+    /// are forgotten.
+    pub fn add(&mut self, text: &str, file: Option<String>) -> (Rc<str>, usize) {
+        self.texts.retain(|text| text.text.strong_count() > 0);
+        let text: Rc<str> = Rc::from(text);
+        let offset = self.end;
+        // One past the end, so that the span at the end of a text is in it.
+        self.end += text.len() + 1;
+        self.texts.push(Text {
+            offset,
+            text: Rc::downgrade(&text),
+            file,
+        });
+        (text, offset)
+    }
+
+    /// Renders the line `span` points at, as `Span::render` does, or nothing
+    /// if its text is gone.
+    pub fn render(&self, span: Span) -> String {
+        let index = self.texts.partition_point(|text| text.offset <= span.start);
+        let Text { offset, text, file } = match index.checked_sub(1) {
+            Some(index) => &self.texts[index],
+            None => return String::new(),
+        };
+        match text.upgrade() {
+            Some(text) if span.start - offset <= text.len() => Span {
+                start: span.start - offset,
+                end: span.end - offset,
+                ..span
+            }
+            .render(&text, file.as_deref()),
+            _ => String::new(),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+/// A region of source text. `start..end` are byte offsets into the program
+/// text, `line` and `column` (both starting at 1) locate `start` for humans.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// The span running from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..self
+        }
+    }
+
+    /// Renders the source line containing the start of the span with the
+    /// spanned text underlined, in the style of rustc:
+    ///
+    /// ```text
+    ///  --> 2:7
+    ///   |
+    /// 2 | print 1 + nil;
+    ///   |       ^~~~~~~
+    /// ```
+    ///
+    /// The position is preceded by `file`, the name of the file `source` was
+    /// read from, if it has one. Spans that fall outside of `source`, and the
+    /// default span of errors with no place in the source, render as nothing.
+    pub fn render(&self, source: &str, file: Option<&str>) -> String {
+        if self.line == 0 || self.start > source.len() || !source.is_char_boundary(self.start) {
+            return String::new();
+        }
+        let line_start = source[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[self.start..]
+            .find('\n')
+            .map_or(source.len(), |i| self.start + i);
+        let text = source[line_start..line_end].trim_end_matches('\r');
+
+        // Spans covering several lines are underlined up to the end of the first one.
+        let end = self.end.clamp(self.start, line_start + text.len());
+        // Tabs are kept so the underline lines up with the text above it.
+        let indent: String = source[line_start..self.start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source[self.start..end].chars().count().max(1);
+
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let file = file.map_or(String::new(), |file| format!("{}:", file));
+        format!(
+            "{gutter}--> {file}{}:{}\n{gutter} |\n{number} | {text}\n{gutter} | {}^{}",
+            self.line,
+            self.column,
+            indent,
+            "~".repeat(width - 1),
+        )
+    }
+}
@@ -0,0 +1,197 @@
+//! A cycle collector for the reference counted objects of both backends.
+//!
+//! Values are shared through `Rc`, which frees everything except cycles, such
+//! as a closure stored in the scope it captures. The heap keeps a weak
+//! reference to every object that can be part of a cycle. A collection traces
+//! the graph these objects span and counts, for every object in it, the
+//! references coming from inside the graph. An object whose strong count is
+//! higher is also referenced from elsewhere: a variable of the running
+//! program, a Rust local or the embedder. Everything reachable from those
+//! objects is live; the rest can only be reached through cycles, and is freed
+//! by clearing the references it holds. Collecting needs no knowledge of the
+//! roots, so it can run at any point.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+/// Don't collect before this many objects were allocated.
+const MIN_THRESHOLD: usize = 1024;
+
+/// An object the collector can look into.
+pub trait Trace {
+    /// Reports every `Rc` this object holds to another traced object.
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Drops the references this object holds, which breaks the cycles it is
+    /// part of. Only called on garbage.
+    fn clear(&self) {}
+}
+
+struct Node {
+    object: Weak<dyn Trace>,
+    strong: usize,
+    /// References from other objects of the graph.
+    internal: usize,
+    children: Vec<usize>,
+}
+
+/// Records the references of the objects being traced. Objects are
+/// identified by address.
+#[derive(Default)]
+pub struct Tracer {
+    nodes: HashMap<usize, Node>,
+    pending: Vec<usize>,
+    children: Vec<usize>,
+}
+
+impl Tracer {
+    pub fn edge<T: Trace + 'static>(&mut self, object: &Rc<T>) {
+        let weak: Weak<T> = Rc::downgrade(object);
+        self.edge_weak(weak as Weak<dyn Trace>, Rc::strong_count(object));
+    }
+
+    /// Like `edge`, for trait objects. Callers upcast them to `dyn Trace`,
+    /// which `edge` can't do for them.
+    pub fn edge_weak(&mut self, object: Weak<dyn Trace>, strong: usize) {
+        let id = self.discover(object, strong);
+        if let Some(node) = self.nodes.get_mut(&id) {
+            node.internal += 1;
+        }
+        self.children.push(id);
+    }
+
+    fn discover(&mut self, object: Weak<dyn Trace>, strong: usize) -> usize {
+        let id = Weak::as_ptr(&object) as *const () as usize;
+        self.nodes.entry(id).or_insert_with(|| {
+            self.pending.push(id);
+            Node {
+                object,
+                strong,
+                internal: 0,
+                children: vec![],
+            }
+        });
+        id
+    }
+}
+
+/// What the collector did so far. The counts depend on the backend: both
+/// track instances, lists and maps, but the tree-walker also tracks the
+/// scopes functions run in and the VM the upvalues closures capture.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    /// Objects freed by all collections.
+    pub collected: usize,
+    /// Objects the heap tracked after the last collection.
+    pub tracked: usize,
+}
+
+impl GcStats {
+    /// The entries of the map `gc()` returns after a collection that freed
+    /// `collected` objects.
+    pub fn report(&self, collected: usize) -> [(&'static str, f64); 4] {
+        [
+            ("collected", collected as f64),
+            ("collections", self.collections as f64),
+            ("total collected", self.collected as f64),
+            ("tracked", self.tracked as f64),
+        ]
+    }
+}
+
+/// The objects that may be part of cycles.
+pub struct Heap {
+    objects: Vec<Weak<dyn Trace>>,
+    next_collection: usize,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: vec![],
+            next_collection: MIN_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Starts tracking `object`, collecting first when enough objects were
+    /// allocated since the last collection.
+    pub fn track<T: Trace + 'static>(&mut self, object: &Rc<T>) {
+        if self.objects.len() >= self.next_collection {
+            self.collect();
+        }
+        let weak: Weak<T> = Rc::downgrade(object);
+        self.objects.push(weak as Weak<dyn Trace>);
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// How many objects are tracked, including the ones freed since the last
+    /// collection.
+    pub fn tracked(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Frees the objects that are only reachable through cycles and returns
+    /// how many there were.
+    pub fn collect(&mut self) -> usize {
+        self.objects.retain(|object| object.strong_count() > 0);
+
+        let mut tracer = Tracer::default();
+        for object in &self.objects {
+            tracer.discover(object.clone(), object.strong_count());
+        }
+        while let Some(id) = tracer.pending.pop() {
+            let object = tracer.nodes[&id].object.upgrade();
+            if let Some(object) = object {
+                object.trace(&mut tracer);
+            }
+            let children = std::mem::take(&mut tracer.children);
+            if let Some(node) = tracer.nodes.get_mut(&id) {
+                node.children = children;
+            }
+        }
+
+        let mut live: Vec<usize> = tracer
+            .nodes
+            .iter()
+            .filter(|(_, node)| node.strong > node.internal)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut marked = HashSet::new();
+        while let Some(id) = live.pop() {
+            if marked.insert(id) {
+                live.extend(&tracer.nodes[&id].children);
+            }
+        }
+
+        let garbage: Vec<Rc<dyn Trace>> = tracer
+            .nodes
+            .iter()
+            .filter(|(id, _)| !marked.contains(*id))
+            .filter_map(|(_, node)| node.object.upgrade())
+            .collect();
+        let collected = garbage.len();
+        for object in &garbage {
+            object.clear();
+        }
+        drop(garbage);
+
+        self.objects.retain(|object| object.strong_count() > 0);
+        self.next_collection = MIN_THRESHOLD.max(self.objects.len() * 2);
+        self.stats.collections += 1;
+        self.stats.collected += collected;
+        self.stats.tracked = self.objects.len();
+        collected
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
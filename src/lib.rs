@@ -0,0 +1,16 @@
+//! A Lox interpreter based on "Crafting Interpreters". `Lox` is the entry
+//! point for embedding it; the modules expose the individual stages.
+
+pub mod ast;
+pub mod common;
+pub mod gc;
+mod lox;
+pub mod module;
+pub mod runtime;
+pub mod sandbox;
+pub mod scanner;
+pub mod vm;
+
+pub use common::{Error, Literal, RuntimeError};
+pub use lox::{Lox, LoxError};
+pub use sandbox::{Limit, Sandbox};
@@ -0,0 +1,306 @@
+use crate::common::{Error, Literal, RuntimeError, Sources, Span};
+use crate::gc::GcStats;
+use crate::module;
+use crate::runtime::{
+    Args, Arity, Interpreter, NativeClass, NativeFunction, NativeModule, NativeResult, Resolver,
+};
+use crate::sandbox::Sandbox;
+use crate::vm::{Compiler, Value, Vm};
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// The engine that executes resolved programs: the original tree-walking
+/// interpreter, or the bytecode compiler and virtual machine.
+enum Backend {
+    TreeWalker(Interpreter),
+    Vm(Vm),
+}
+
+/// Why a call to `Lox::eval` failed. The report is the rendered diagnostic,
+/// quoting the source the error points at, and is what `Display` prints.
+#[derive(Debug)]
+pub enum LoxError {
+    /// The program was rejected before any of it ran, by the scanner, parser,
+    /// resolver or bytecode compiler.
+    Compile {
+        errors: Vec<Error>,
+        report: String,
+    },
+    Runtime {
+        error: RuntimeError,
+        report: String,
+    },
+    /// The program ran out of a limit of its sandbox, which `error.limit`
+    /// names.
+    Limit {
+        error: RuntimeError,
+        report: String,
+    },
+    /// The script given to `Lox::eval_file` couldn't be read.
+    Io {
+        error: io::Error,
+        report: String,
+    },
+}
+
+impl LoxError {
+    /// The exit status the command line interpreter uses for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoxError::Compile { .. } => 65,
+            LoxError::Runtime { .. } | LoxError::Limit { .. } => 70,
+            LoxError::Io { .. } => 66,
+        }
+    }
+}
+
+impl Display for LoxError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            LoxError::Compile { report, .. }
+            | LoxError::Runtime { report, .. }
+            | LoxError::Limit { report, .. }
+            | LoxError::Io { report, .. } => {
+                write!(f, "{}", report)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+/// A Lox interpreter that can be embedded in a Rust program. Every call to
+/// `eval` runs in the same global scope, so declarations made by one are
+/// visible to the next.
+///
+/// Only strings, numbers, booleans, nil, lists and maps can be passed in and
+/// out of the virtual machine backend.
+pub struct Lox {
+    backend: Backend,
+    resolver: Resolver,
+    /// The texts evaluated and imported so far, which spans point into. Each
+    /// is kept while the functions declared in it exist, so that errors can
+    /// quote the source of earlier calls.
+    sources: Rc<RefCell<Sources>>,
+    run_in_repl: bool,
+}
+
+impl Lox {
+    /// An engine running programs on the tree-walking interpreter. It
+    /// recurses on the caller's stack for every Lox call, moving on to stack
+    /// segments of its own when that runs low, so deep recursion can't
+    /// overflow it; calls nest at most `sandbox::MAX_CALL_DEPTH` deep.
+    pub fn new() -> Self {
+        Self::with_backend(Backend::TreeWalker(Interpreter::new()))
+    }
+
+    /// An engine compiling programs to bytecode for the virtual machine.
+    pub fn with_vm() -> Self {
+        Self::with_backend(Backend::Vm(Vm::new()))
+    }
+
+    /// An engine running untrusted programs on the tree-walking interpreter,
+    /// with only what `sandbox` allows.
+    pub fn sandboxed(sandbox: &Sandbox) -> Self {
+        Self::with_backend(Backend::TreeWalker(Interpreter::sandboxed(sandbox)))
+    }
+
+    /// An engine running untrusted programs on the virtual machine, with only
+    /// what `sandbox` allows.
+    pub fn sandboxed_vm(sandbox: &Sandbox) -> Self {
+        Self::with_backend(Backend::Vm(Vm::sandboxed(sandbox)))
+    }
+
+    fn with_backend(mut backend: Backend) -> Self {
+        let sources = match &mut backend {
+            Backend::TreeWalker(interpreter) => interpreter.modules().sources(),
+            Backend::Vm(vm) => vm.modules().sources(),
+        };
+        Self {
+            backend,
+            resolver: Resolver::new(),
+            sources,
+            run_in_repl: false,
+        }
+    }
+
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.set_output(Box::new(output)),
+            Backend::Vm(vm) => vm.set_output(Box::new(output)),
+        }
+    }
+
+    /// Sends what `eprint` writes to `output` instead of stderr.
+    pub fn set_error_output(&mut self, output: impl Write + 'static) {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.set_error_output(Box::new(output)),
+            Backend::Vm(vm) => vm.set_error_output(Box::new(output)),
+        }
+    }
+
+    /// In REPL mode the values of expression statements that are not nested
+    /// in a block are printed, like the interactive prompt does.
+    pub fn set_repl_mode(&mut self, run_in_repl: bool) {
+        self.run_in_repl = run_in_repl;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Literal> {
+        match &self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.get_global(name),
+            Backend::Vm(vm) => vm.get_global(name).and_then(|value| value.to_literal()),
+        }
+    }
+
+    /// Defines a global variable, replacing any previous value. Fails if the
+    /// virtual machine can't represent `value`.
+    pub fn set_global(&mut self, name: &str, value: Literal) -> Result<(), LoxError> {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.set_global(name, value),
+            Backend::Vm(vm) => match Value::from_literal(&value) {
+                Some(value) => vm.set_global(name, value),
+                None => return Err(unrepresentable(&self.sources.borrow(), &value)),
+            },
+        }
+        Ok(())
+    }
+
+    /// Defines a global function implemented by a Rust closure, which
+    /// receives the arguments converted to `Literal`s. Changes it makes to
+    /// the lists and maps it receives are seen by the script on both
+    /// backends.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: impl Into<Arity>,
+        function: impl Fn(&Args) -> NativeResult<Literal> + 'static,
+    ) {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.define_native(name, arity, function),
+            Backend::Vm(vm) => vm.define_native(NativeFunction::new(name, arity, function)),
+        }
+    }
+
+    /// Defines a global class whose methods are implemented in Rust. Script
+    /// classes can inherit from it and override its methods.
+    pub fn define_class(&mut self, class: NativeClass) {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.define_class(class),
+            Backend::Vm(vm) => vm.define_class(class),
+        }
+    }
+
+    /// Defines every function and constant of `module` as a global. Fails if
+    /// the virtual machine can't represent one of the constants.
+    pub fn define_module(&mut self, module: NativeModule) -> Result<(), LoxError> {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.define_module(module),
+            Backend::Vm(vm) => vm
+                .define_module(module)
+                .map_err(|value| unrepresentable(&self.sources.borrow(), &value))?,
+        }
+        Ok(())
+    }
+
+    /// Frees the objects that are only reachable through reference cycles and
+    /// returns how many there were. Collections also run on their own as the
+    /// program allocates.
+    pub fn collect_garbage(&mut self) -> usize {
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.collect_garbage(),
+            Backend::Vm(vm) => vm.collect_garbage(),
+        }
+    }
+
+    /// What the collector did so far, counted as the backend tracks objects,
+    /// so the numbers for a program differ between the two.
+    pub fn gc_stats(&self) -> GcStats {
+        match &self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.gc_stats(),
+            Backend::Vm(vm) => vm.gc_stats(),
+        }
+    }
+
+    /// Runs `source` and returns the value of its final statement if that is
+    /// an expression statement, or nil otherwise.
+    pub fn eval(&mut self, source: &str) -> Result<Literal, LoxError> {
+        self.run(source, None)
+    }
+
+    /// Runs `source`, read from `file` if it came from one, which errors in it
+    /// then name.
+    fn run(&mut self, source: &str, file: Option<String>) -> Result<Literal, LoxError> {
+        // Held until the errors of the run are rendered.
+        let (text, offset) = self.sources.borrow_mut().add(source, file);
+        let statements = module::parse(&text, offset, &mut self.resolver)
+            .map_err(|errors| compile_error(&self.sources.borrow(), errors))?;
+
+        let result = match &mut self.backend {
+            Backend::TreeWalker(interpreter) => {
+                interpreter.interpret(&statements, self.run_in_repl)
+            }
+            Backend::Vm(vm) => {
+                let function = Compiler::compile(&statements, self.run_in_repl)
+                    .map_err(|err| compile_error(&self.sources.borrow(), vec![err]))?;
+                match vm.interpret(function) {
+                    Ok(value) => {
+                        return value
+                            .to_literal()
+                            .ok_or_else(|| unrepresentable(&self.sources.borrow(), &value))
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+        };
+        result.map_err(|error| {
+            let report = error.render(&self.sources.borrow());
+            match error.limit {
+                Some(_) => LoxError::Limit { error, report },
+                None => LoxError::Runtime { error, report },
+            }
+        })
+    }
+
+    /// Runs the script at `path` like `eval`. Its imports are looked up
+    /// relative to its directory.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> Result<Literal, LoxError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|error| LoxError::Io {
+            report: format!("Can't read '{}': {}.", path.display(), error),
+            error,
+        })?;
+        match &mut self.backend {
+            Backend::TreeWalker(interpreter) => interpreter.modules().set_script(path),
+            Backend::Vm(vm) => vm.modules().set_script(path),
+        }
+        self.run(&source, Some(path.display().to_string()))
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reports errors in the order they appear in the source.
+fn compile_error(sources: &Sources, mut errors: Vec<Error>) -> LoxError {
+    let report = module::render_errors(sources, &mut errors);
+    LoxError::Compile { errors, report }
+}
+
+fn unrepresentable(sources: &Sources, value: &dyn Display) -> LoxError {
+    let error = RuntimeError::new(
+        Span::default(),
+        format!("Can't pass {} between the virtual machine and Rust.", value),
+    );
+    LoxError::Runtime {
+        report: error.render(sources),
+        error,
+    }
+}
@@ -1,74 +1,73 @@
-mod ast;
-mod common;
-mod runtime;
-mod scanner;
-
-use ast::Parser;
-use common::Error;
-use runtime::Interpreter;
-use runtime::Resolver;
-use scanner::Scanner;
+use rust_lox::scanner::Scanner;
+use rust_lox::{Literal, Lox};
+use std::cell::RefCell;
 use std::env;
-use std::fs;
 use std::io;
+use std::io::Write;
+use std::mem;
 use std::process;
-
+use std::rc::Rc;
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 2 {
-        println!("Usage: rust-lox [script]");
-        process::exit(64);
-    } else if args.len() == 2 {
-        run_file(&args[1]);
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mut lox = if args.first().is_some_and(|arg| arg == "--vm") {
+        args.remove(0);
+        Lox::with_vm()
     } else {
-        run_prompt();
+        Lox::new()
+    };
+    // The arguments after the script are handed to it as the `args` list.
+    let script_args = args
+        .iter()
+        .skip(1)
+        .map(|arg| Literal::String(arg.clone()))
+        .collect();
+    let _ = lox.set_global("args", Literal::List(Rc::new(RefCell::new(script_args))));
+    match args.first() {
+        Some(script) => run_file(script, lox),
+        None => run_prompt(lox),
     }
 }
 
-fn run_file(path: &str) {
-    let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
-    run(contents, false);
+fn run_file(path: &str, mut lox: Lox) {
+    if let Err(err) = lox.eval_file(path) {
+        println!("{}", err);
+        process::exit(err.exit_code());
+    }
 }
 
-fn run_prompt() {
+fn run_prompt(mut lox: Lox) {
+    // The engine outlives a single line so that declarations made on one
+    // line are visible on the next.
+    lox.set_repl_mode(true);
+    let mut source = String::new();
     loop {
-        println!("> ");
+        if source.is_empty() {
+            print!("> ");
+        } else {
+            print!(". ");
+        }
+        let _ = io::stdout().flush();
+
         let mut line = String::new();
         match io::stdin().read_line(&mut line) {
-            Ok(_) => run(line, true),
-            Err(_) => break,
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                source.push_str(&line);
+                if !is_incomplete(&source) {
+                    if let Err(err) = lox.eval(&mem::take(&mut source)) {
+                        println!("{}", err);
+                    }
+                }
+            }
         }
     }
 }
 
-fn run(source: std::string::String, run_in_repl: bool) {
-    let mut scanner = Scanner::new(source);
+/// Input is incomplete while it has unclosed brackets of any kind, strings
+/// or block comments, in which case the REPL keeps reading lines before
+/// running it.
+fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source.to_string(), 0);
     scanner.scan_tokens();
-
-    if scanner.had_error {
-        process::exit(65);
-    }
-
-    let mut parser = Parser::new(scanner.tokens);
-    let mut statements = parser.parse();
-    let mut resolver = Resolver::new();
-    for statement in &mut statements {
-        match resolver.resolve_statement(statement) {
-            Ok(_) => {}
-            Err(err) => {
-                Error::error(err.line, err.message);
-                return;
-            }
-        }
-    }
-
-    let mut interpreter = Interpreter::new();
-    for statement in &statements {
-        match interpreter.evaluate_statement(statement, run_in_repl) {
-            Ok(_) => {}
-            Err(err) => {
-                Error::error(err.line, err.message);
-            }
-        }
-    }
+    scanner.is_incomplete()
 }
@@ -0,0 +1,207 @@
+//! Loading the files named by `import` statements, for both backends.
+//!
+//! Each module runs once, in a global scope of its own, and the backend
+//! caches whatever represents that scope. Later imports of the same file reuse
+//! it. Paths are looked up relative to the directory of the importing file,
+//! then in the directories listed in the `LOX_PATH` environment variable.
+
+use crate::ast::{Parser, Statement};
+use crate::common::{Error, RuntimeError, RuntimeResult, Sources, Span};
+use crate::runtime::Resolver;
+use crate::scanner::Scanner;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Scans, parses and resolves `text`, whose spans start at `offset`, as
+/// given by `Sources::add`.
+pub fn parse(
+    text: &Rc<str>,
+    offset: usize,
+    resolver: &mut Resolver,
+) -> Result<Vec<Statement>, Vec<Error>> {
+    let mut scanner = Scanner::new(text.to_string(), offset);
+    scanner.scan_tokens();
+    let mut errors = mem::take(&mut scanner.errors);
+
+    let mut parser = Parser::new(scanner.tokens, text.clone());
+    let mut statements = parser.parse();
+    errors.append(&mut parser.errors);
+
+    if let Err(mut resolve_errors) = resolver.resolve(&mut statements) {
+        errors.append(&mut resolve_errors);
+    }
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Renders `errors` in the order they appear in the source.
+pub fn render_errors(sources: &Sources, errors: &mut [Error]) -> String {
+    errors.sort_by_key(|err| err.span.start);
+    errors
+        .iter()
+        .map(|err| err.render(sources))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// A file whose top level is running.
+struct Running {
+    path: PathBuf,
+    /// The path as it was found, which errors show and imports from the file
+    /// are relative to.
+    found: PathBuf,
+}
+
+/// What `Modules::begin` found for an import.
+pub enum Import<M> {
+    /// The module already ran; this is its global scope.
+    Cached(M),
+    /// The module has to run. The backend calls `Modules::finish` with its
+    /// global scope when it has, or `Modules::cancel` if it failed.
+    Load(Vec<Statement>),
+}
+
+/// The modules imported so far, each represented by its global scope `M`.
+pub struct Modules<M> {
+    sources: Rc<RefCell<Sources>>,
+    /// The texts of the modules that were loaded, which their errors quote.
+    texts: Vec<Rc<str>>,
+    search_path: Vec<PathBuf>,
+    cache: HashMap<PathBuf, M>,
+    /// The script given to `Lox::eval_file`, if any.
+    script: Option<Running>,
+    /// The modules being imported, innermost last.
+    running: Vec<Running>,
+    /// Whether `import` statements may run files.
+    imports: bool,
+}
+
+impl<M: Clone> Modules<M> {
+    pub fn new() -> Self {
+        let search_path = env::var_os("LOX_PATH")
+            .map(|paths| env::split_paths(&paths).collect())
+            .unwrap_or_default();
+        Self {
+            sources: Rc::new(RefCell::new(Sources::new())),
+            texts: vec![],
+            search_path,
+            cache: HashMap::new(),
+            script: None,
+            running: vec![],
+            imports: true,
+        }
+    }
+
+    /// The source texts spans point into.
+    pub fn sources(&self) -> Rc<RefCell<Sources>> {
+        self.sources.clone()
+    }
+
+    /// Makes imports at the top level of the program relative to `script`,
+    /// and importing it again a cycle.
+    pub fn set_script(&mut self, script: &Path) {
+        self.script = Some(Running {
+            path: script
+                .canonicalize()
+                .unwrap_or_else(|_| script.to_path_buf()),
+            found: script.to_path_buf(),
+        });
+    }
+
+    /// Makes every import fail, for sandboxed programs.
+    pub fn forbid_imports(&mut self) {
+        self.imports = false;
+    }
+
+    /// Looks up the module `import` names and parses it, unless it ran before.
+    /// `span` locates the import for errors.
+    pub fn begin(&mut self, import: &str, span: Span) -> RuntimeResult<Import<M>> {
+        if !self.imports {
+            return Err(RuntimeError::new(
+                span,
+                String::from("Imports are not allowed in this sandbox."),
+            ));
+        }
+        let found = self
+            .find(import)
+            .ok_or_else(|| RuntimeError::new(span, format!("Can't find module '{}'.", import)))?;
+        let path = found.canonicalize().unwrap_or_else(|_| found.clone());
+        if let Some(module) = self.cache.get(&path) {
+            return Ok(Import::Cached(module.clone()));
+        }
+        let name = found.display().to_string();
+        let chain: Vec<&Running> = self.script.iter().chain(&self.running).collect();
+        if let Some(start) = chain.iter().position(|running| running.path == path) {
+            let cycle = chain[start..]
+                .iter()
+                .map(|running| running.found.display().to_string())
+                .chain(std::iter::once(name.clone()))
+                .collect::<Vec<String>>()
+                .join(" -> ");
+            return Err(RuntimeError::new(span, format!("Import cycle: {}.", cycle)));
+        }
+
+        let source = fs::read_to_string(&path).map_err(|err| {
+            RuntimeError::new(span, format!("Can't read module '{}': {}.", name, err))
+        })?;
+        let (text, offset) = self.sources.borrow_mut().add(&source, Some(name.clone()));
+        let statements = parse(&text, offset, &mut Resolver::new()).map_err(|mut errors| {
+            let report = render_errors(&self.sources.borrow(), &mut errors);
+            RuntimeError::new(span, format!("Module '{}' has errors:\n{}", name, report))
+        })?;
+        self.texts.push(text);
+        self.running.push(Running { path, found });
+        Ok(Import::Load(statements))
+    }
+
+    /// Caches the global scope of the module that finished running.
+    pub fn finish(&mut self, module: M) {
+        if let Some(running) = self.running.pop() {
+            self.cache.insert(running.path, module);
+        }
+    }
+
+    /// Forgets the module that failed to run, so that importing it again runs
+    /// it again.
+    pub fn cancel(&mut self) {
+        self.running.pop();
+    }
+
+    /// Forgets every module that was still running when an error stopped the
+    /// program.
+    pub fn cancel_all(&mut self) {
+        self.running.clear();
+    }
+
+    fn find(&self, import: &str) -> Option<PathBuf> {
+        let import = Path::new(import);
+        if import.is_absolute() {
+            return Some(import.to_path_buf()).filter(|path| path.is_file());
+        }
+        let importer_dir = self
+            .running
+            .last()
+            .or(self.script.as_ref())
+            .and_then(|running| running.found.parent())
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        std::iter::once(importer_dir)
+            .chain(self.search_path.iter().cloned())
+            .map(|dir| dir.join(import))
+            .find(|path| path.is_file())
+    }
+}
+
+impl<M: Clone> Default for Modules<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
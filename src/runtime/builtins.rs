@@ -1,19 +1,411 @@
-use super::{Interpreter, Literal, LoxCallable, Result};
+use super::list;
+use super::native::{ListRef, MapRef, NativeError, NativeModule};
+use super::{Arity, Interpreter, Literal, LoxCallable, LoxMap, MapKey, RuntimeResult};
+use crate::gc::{Trace, Tracer};
 use chrono::offset::Utc;
+use std::cell::RefCell;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
 
-#[derive(Debug)]
-pub struct Clock;
+/// The list and map natives. `len` also counts the characters of strings.
+/// `str`, `num` and `bool` convert values, and `type` and `instanceof` tell
+/// what kind they are.
+pub fn core() -> NativeModule {
+    NativeModule::new("core")
+        .function("len", 1, |args| {
+            let len = match args.value(0) {
+                Literal::Map(map) => map.borrow().len(),
+                Literal::String(text) => text.chars().count(),
+                _ => args.get::<ListRef>(0)?.borrow().len(),
+            };
+            Ok(Literal::Number(len as f64))
+        })
+        .function("push", 2, |args| {
+            let list = args.get::<ListRef>(0)?;
+            args.check_length(list.borrow().len() + 1)?;
+            list.borrow_mut().push(args.value(1).clone());
+            Ok(Literal::Nil)
+        })
+        .function("pop", 1, |args| {
+            let list = args.get::<ListRef>(0)?;
+            let last = list.borrow_mut().pop();
+            Ok(last.ok_or("Can't pop from an empty list.")?)
+        })
+        .function("insert", 3, |args| {
+            let list = args.get::<ListRef>(0)?;
+            let len = list.borrow().len();
+            let position = list::boundary_index(args.value(1).as_number(), len, args.span())?;
+            args.check_length(len + 1)?;
+            list.borrow_mut().insert(position, args.value(2).clone());
+            Ok(Literal::Nil)
+        })
+        .function("remove", 2, |args| {
+            let list = args.get::<ListRef>(0)?;
+            let len = list.borrow().len();
+            let position = list::element_index(args.value(1).as_number(), len, args.span())?;
+            let removed = list.borrow_mut().remove(position);
+            Ok(removed)
+        })
+        .function("keys", 1, |args| {
+            let map = args.get::<MapRef>(0)?;
+            let keys = map
+                .borrow()
+                .iter()
+                .map(|(key, _)| key.to_literal())
+                .collect();
+            Ok(Literal::List(Rc::new(RefCell::new(keys))))
+        })
+        .function("values", 1, |args| {
+            let map = args.get::<MapRef>(0)?;
+            let values = map
+                .borrow()
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect();
+            Ok(Literal::List(Rc::new(RefCell::new(values))))
+        })
+        .function("has", 2, |args| {
+            let map = args.get::<MapRef>(0)?;
+            let key = MapKey::from_literal(args.value(1), args.span())?;
+            let has = map.borrow().contains_key(&key);
+            Ok(Literal::Boolean(has))
+        })
+        .function("delete", 2, |args| {
+            let map = args.get::<MapRef>(0)?;
+            let key = MapKey::from_literal(args.value(1), args.span())?;
+            let removed = map.borrow_mut().remove(&key);
+            Ok(Literal::Boolean(removed.is_some()))
+        })
+        .function("str", 1, |args| {
+            Ok(Literal::String(args.value(0).to_string()))
+        })
+        .function("num", 1, |args| match args.value(0) {
+            Literal::Number(n) => Ok(Literal::Number(*n)),
+            Literal::String(text) => Ok(Literal::Number(parse_number(text)?)),
+            _ => Err(NativeError::Argument {
+                index: 0,
+                expected: String::from("a number or a string"),
+            }),
+        })
+        .function("bool", 1, |args| {
+            Ok(Literal::Boolean(args.value(0).is_truthy()))
+        })
+        .function("type", 1, |args| {
+            let name = match args.value(0) {
+                Literal::String(_) => "string",
+                Literal::Number(_) => "number",
+                Literal::Boolean(_) => "boolean",
+                Literal::Nil => "nil",
+                Literal::Callable(_) => "function",
+                Literal::Class(_) => "class",
+                Literal::Instance(_) => "instance",
+                Literal::List(_) => "list",
+                Literal::Map(_) => "map",
+            };
+            Ok(Literal::String(name.to_string()))
+        })
+        .function("instanceof", 2, |args| {
+            let class = match args.value(1) {
+                Literal::Class(class) => class,
+                _ => {
+                    return Err(NativeError::Argument {
+                        index: 1,
+                        expected: String::from("a class"),
+                    })
+                }
+            };
+            let is_instance = match args.value(0) {
+                Literal::Instance(instance) => instance.class().is_subclass_of(class),
+                _ => false,
+            };
+            Ok(Literal::Boolean(is_instance))
+        })
+}
 
-impl LoxCallable for Clock {
-    fn call(&self, _: &mut Interpreter, _: Vec<Literal>) -> Result<Literal> {
+/// Converts the text `num` is given, which may be surrounded by whitespace.
+/// Infinite and NaN results are refused, since Lox can't write them either.
+pub fn parse_number(text: &str) -> Result<f64, String> {
+    text.trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| format!("Can't convert '{}' to a number.", text))
+}
+
+/// `clock`, the current time in seconds.
+pub fn time() -> NativeModule {
+    NativeModule::new("time").function("clock", 0, |_| {
         Ok(Literal::Number(Utc::now().timestamp() as f64))
+    })
+}
+
+/// The math functions and the constants `PI` and `E`. Angles are in radians.
+/// Arguments are read with `Args::get` and `Args::rest`, so passing anything
+/// but numbers fails with "Argument N must be a number."
+pub fn math() -> NativeModule {
+    let unary = [
+        ("sqrt", f64::sqrt as fn(f64) -> f64),
+        ("abs", f64::abs),
+        ("floor", f64::floor),
+        ("ceil", f64::ceil),
+        ("round", f64::round),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("asin", f64::asin),
+        ("acos", f64::acos),
+        ("atan", f64::atan),
+        ("exp", f64::exp),
+        ("log", f64::ln),
+        ("log10", f64::log10),
+        ("log2", f64::log2),
+    ];
+    unary
+        .into_iter()
+        .fold(NativeModule::new("math"), |module, (name, function)| {
+            module.function(name, 1, move |args| {
+                Ok(Literal::Number(function(args.get::<f64>(0)?)))
+            })
+        })
+        .function("pow", 2, |args| {
+            let base = args.get::<f64>(0)?;
+            Ok(Literal::Number(base.powf(args.get::<f64>(1)?)))
+        })
+        .function("atan2", 2, |args| {
+            let y = args.get::<f64>(0)?;
+            Ok(Literal::Number(y.atan2(args.get::<f64>(1)?)))
+        })
+        .function("min", Arity::AtLeast(1), |args| {
+            let numbers = args.rest::<f64>(0)?;
+            Ok(Literal::Number(
+                numbers.into_iter().fold(f64::INFINITY, f64::min),
+            ))
+        })
+        .function("max", Arity::AtLeast(1), |args| {
+            let numbers = args.rest::<f64>(0)?;
+            Ok(Literal::Number(
+                numbers.into_iter().fold(f64::NEG_INFINITY, f64::max),
+            ))
+        })
+        .constant("PI", Literal::Number(std::f64::consts::PI))
+        .constant("E", Literal::Number(std::f64::consts::E))
+}
+
+/// The string functions. Positions count characters, not bytes, so they
+/// agree with `len` for any text.
+pub fn string() -> NativeModule {
+    NativeModule::new("string")
+        .function("substr", 3, |args| {
+            let text: String = args.get(0)?;
+            let len = text.chars().count();
+            let start = list::char_boundary_index(args.value(1).as_number(), len, args.span())?;
+            let end = list::char_boundary_index(args.value(2).as_number(), len, args.span())?;
+            if start > end {
+                return Err(format!("Substring start {} is after its end {}.", start, end).into());
+            }
+            let substring = text.chars().skip(start).take(end - start).collect();
+            Ok(Literal::String(substring))
+        })
+        .function("char_at", 2, |args| {
+            let text: String = args.get(0)?;
+            let len = text.chars().count();
+            let position = list::char_index(args.value(1).as_number(), len, args.span())?;
+            Ok(Literal::String(
+                text.chars().nth(position).into_iter().collect(),
+            ))
+        })
+        .function("index_of", 2, |args| {
+            let text: String = args.get(0)?;
+            let part: String = args.get(1)?;
+            let position = text
+                .find(&part)
+                .map_or(-1.0, |byte| text[..byte].chars().count() as f64);
+            Ok(Literal::Number(position))
+        })
+        .function("contains", 2, |args| {
+            let text: String = args.get(0)?;
+            Ok(Literal::Boolean(text.contains(&args.get::<String>(1)?)))
+        })
+        .function("starts_with", 2, |args| {
+            let text: String = args.get(0)?;
+            Ok(Literal::Boolean(text.starts_with(&args.get::<String>(1)?)))
+        })
+        .function("ends_with", 2, |args| {
+            let text: String = args.get(0)?;
+            Ok(Literal::Boolean(text.ends_with(&args.get::<String>(1)?)))
+        })
+        .function("split", 2, |args| {
+            let text: String = args.get(0)?;
+            let separator: String = args.get(1)?;
+            // An empty separator splits the text into its characters.
+            let parts = if separator.is_empty() {
+                text.chars()
+                    .map(|c| Literal::String(c.to_string()))
+                    .collect()
+            } else {
+                text.split(&separator)
+                    .map(|part| Literal::String(part.to_string()))
+                    .collect()
+            };
+            Ok(Literal::List(Rc::new(RefCell::new(parts))))
+        })
+        .function("join", 2, |args| {
+            let list = args.get::<ListRef>(0)?;
+            let separator: String = args.get(1)?;
+            let parts = list
+                .borrow()
+                .iter()
+                .map(|element| match element {
+                    Literal::String(part) => Ok(part.clone()),
+                    _ => Err("Can only join lists of strings."),
+                })
+                .collect::<Result<Vec<String>, _>>()?;
+            let separators = separator.len() * parts.len().saturating_sub(1);
+            args.check_length(parts.iter().map(String::len).sum::<usize>() + separators)?;
+            Ok(Literal::String(parts.join(&separator)))
+        })
+        .function("trim", 1, |args| {
+            let text: String = args.get(0)?;
+            Ok(Literal::String(text.trim().to_string()))
+        })
+        .function("upper", 1, |args| {
+            let text: String = args.get(0)?;
+            Ok(Literal::String(text.to_uppercase()))
+        })
+        .function("lower", 1, |args| {
+            let text: String = args.get(0)?;
+            Ok(Literal::String(text.to_lowercase()))
+        })
+        .function("replace", 3, |args| {
+            let text: String = args.get(0)?;
+            let from: String = args.get(1)?;
+            let to: String = args.get(2)?;
+            if to.len() > from.len() {
+                let matches = if from.is_empty() {
+                    text.chars().count() + 1
+                } else {
+                    text.matches(&from).count()
+                };
+                args.check_length(
+                    text.len()
+                        .saturating_add(matches.saturating_mul(to.len() - from.len())),
+                )?;
+            }
+            Ok(Literal::String(text.replace(&from, &to)))
+        })
+        .function("ord", 1, |args| {
+            let text: String = args.get(0)?;
+            let mut chars = text.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Literal::Number(c as u32 as f64)),
+                _ => Err(NativeError::Argument {
+                    index: 0,
+                    expected: String::from("a single character"),
+                }),
+            }
+        })
+        .function("chr", 1, |args| {
+            let code: i64 = args.get(0)?;
+            let c = u32::try_from(code)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("{} is not a character code.", code))?;
+            Ok(Literal::String(c.to_string()))
+        })
+}
+
+/// A writer the engine hands to natives, and can replace while they hold it.
+pub type SharedOutput = Rc<RefCell<Box<dyn Write>>>;
+
+/// Reading standard input and files, and writing to the error output and
+/// files. `eprint` writes to `errors`, which is standard error unless the
+/// engine redirects it. Failures are runtime errors, which scripts can catch.
+pub fn io(errors: SharedOutput) -> NativeModule {
+    NativeModule::new("io")
+        .function("read_line", 0, |_| {
+            let mut line = String::new();
+            let read = io::stdin()
+                .read_line(&mut line)
+                .map_err(|err| format!("Can't read from standard input: {}.", err))?;
+            if read == 0 {
+                return Ok(Literal::Nil);
+            }
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Literal::String(line))
+        })
+        .function("read_file", 1, |args| {
+            let path: String = args.get(0)?;
+            let text = fs::read_to_string(&path)
+                .map_err(|err| format!("Can't read file '{}': {}.", path, err))?;
+            Ok(Literal::String(text))
+        })
+        .function("write_file", 2, |args| {
+            let path: String = args.get(0)?;
+            let text: String = args.get(1)?;
+            fs::write(&path, text)
+                .map_err(|err| format!("Can't write file '{}': {}.", path, err))?;
+            Ok(Literal::Nil)
+        })
+        .function("append_file", 2, |args| {
+            let path: String = args.get(0)?;
+            let text: String = args.get(1)?;
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(text.as_bytes()))
+                .map_err(|err| format!("Can't append to file '{}': {}.", path, err))?;
+            Ok(Literal::Nil)
+        })
+        .function("file_exists", 1, |args| {
+            let path: String = args.get(0)?;
+            Ok(Literal::Boolean(Path::new(&path).is_file()))
+        })
+        .function("eprint", 1, move |args| {
+            writeln!(errors.borrow_mut(), "{}", args.value(0))
+                .map_err(|_| "Can't write to the error output.")?;
+            Ok(Literal::Nil)
+        })
+}
+
+/// `gc()` runs the cycle collector and returns a map of statistics: how many
+/// objects this collection freed, how many collections ran so far and how many
+/// objects remain tracked.
+#[derive(Debug)]
+pub struct Gc;
+
+impl Trace for Gc {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl LoxCallable for Gc {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        _args: Vec<Literal>,
+    ) -> RuntimeResult<Literal> {
+        let collected = interpreter.collect_garbage();
+        let mut map = LoxMap::new();
+        for (key, value) in interpreter.gc_stats().report(collected) {
+            map.insert(MapKey::String(key.to_string()), Literal::Number(value));
+        }
+        let map = Rc::new(RefCell::new(map));
+        interpreter.track(&map);
+        Ok(Literal::Map(map))
     }
 
-    fn arity(&self) -> usize {
-        0
+    fn arity(&self) -> Arity {
+        Arity::Exact(0)
     }
 
     fn name(&self) -> String {
-        "clock".to_string()
+        String::from("gc")
     }
 }
@@ -1,16 +1,31 @@
+use super::Arity;
 use super::Interpreter;
 use super::Literal;
-use super::Result;
+use super::RuntimeResult;
+use crate::gc::Trace;
+use std::any::Any;
 use std::fmt;
+use std::rc::Rc;
 
-pub trait LoxCallable: fmt::Debug {
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Literal>) -> Result<Literal>;
-    fn arity(&self) -> usize;
+/// `Any` lets the virtual machine recognize the callables it hands to the
+/// natives it shares with the tree-walker when they come back.
+pub trait LoxCallable: Any + Trace + fmt::Debug {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Literal>,
+    ) -> RuntimeResult<Literal>;
+    fn arity(&self) -> Arity;
     fn name(&self) -> String;
+
+    /// How the callable prints.
+    fn describe(&self) -> String {
+        format!("<fn {}>", self.name())
+    }
 }
 
 impl fmt::Display for dyn LoxCallable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "<fn {}>", self.name())
+        write!(f, "{}", self.describe())
     }
 }
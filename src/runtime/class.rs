@@ -1,30 +1,119 @@
-use super::{Interpreter, Literal, LoxCallable, LoxInstance, Result};
+use super::native::{BoundNativeMethod, NativeClass, NativeMethod};
+use super::{Arity, Interpreter, Literal, LoxCallable, LoxFunction, LoxInstance, RuntimeResult};
+use crate::gc::{Trace, Tracer};
+use std::collections::HashMap;
 use std::rc::Rc;
 
+/// A method declared in Lox or implemented by the host.
 #[derive(Debug, Clone)]
+pub enum Method {
+    Lox(Rc<LoxFunction>),
+    Native(Rc<NativeMethod>),
+}
+
+impl Method {
+    pub fn bind(&self, instance: Rc<LoxInstance>) -> Rc<dyn LoxCallable> {
+        match self {
+            Method::Lox(function) => Rc::new(function.bind(instance)),
+            Method::Native(method) => Rc::new(BoundNativeMethod::new(instance, method.clone())),
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        match self {
+            Method::Lox(function) => function.arity(),
+            Method::Native(method) => method.arity(),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct LoxClass {
     name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
+    native_methods: HashMap<String, Rc<NativeMethod>>,
 }
 
 impl LoxClass {
-    pub fn new(name: String) -> Self {
-        Self { name }
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+            native_methods: HashMap::new(),
+        }
+    }
+
+    pub fn native(class: NativeClass) -> Self {
+        let (name, native_methods) = class.into_parts();
+        Self {
+            name,
+            superclass: None,
+            methods: HashMap::new(),
+            native_methods,
+        }
     }
 
     pub fn to_string(&self) -> String {
         self.name.clone()
     }
+
+    /// Whether this is `class` or inherits from it.
+    pub fn is_subclass_of(&self, class: &LoxClass) -> bool {
+        std::ptr::eq(self, class)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.is_subclass_of(class))
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Method> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(Method::Lox(method.clone()));
+        }
+        if let Some(method) = self.native_methods.get(name) {
+            return Some(Method::Native(method.clone()));
+        }
+        self.superclass
+            .as_ref()
+            .and_then(|superclass| superclass.find_method(name))
+    }
 }
 
 impl LoxCallable for LoxClass {
-    fn call(&self, _interpreter: &mut Interpreter, _args: Vec<Literal>) -> Result<Literal> {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Literal>,
+    ) -> RuntimeResult<Literal> {
         let instance = Rc::new(LoxInstance::new(self.clone()));
+        interpreter.track(&instance);
+        if let Some(initializer) = self.find_method("init") {
+            initializer.bind(instance.clone()).call(interpreter, args)?;
+        }
         Ok(Literal::Instance(instance))
     }
-    fn arity(&self) -> usize {
-        0
+    fn arity(&self) -> Arity {
+        self.find_method("init")
+            .map_or(Arity::Exact(0), |initializer| initializer.arity())
     }
     fn name(&self) -> String {
         self.name.clone()
     }
 }
+
+impl Trace for LoxClass {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(superclass) = &self.superclass {
+            tracer.edge(superclass);
+        }
+        for method in self.methods.values() {
+            tracer.edge(method);
+        }
+    }
+}
@@ -1,11 +1,16 @@
 use super::Literal;
+use crate::gc::{Trace, Tracer};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
+/// A scope of variables. The global scope keeps its variables by name, since
+/// globals are looked up dynamically. Every other scope stores its variables in
+/// `slots`, in the order the resolver declared them, and is accessed by index.
 #[derive(Debug, Clone)]
 pub struct Environment {
     pub values: HashMap<String, Literal>,
+    pub slots: Vec<Literal>,
     pub enclosing: Option<Rc<RefCell<Environment>>>,
 }
 
@@ -13,6 +18,7 @@ impl Environment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            slots: Vec::new(),
             enclosing: None,
         }
     }
@@ -20,6 +26,7 @@ impl Environment {
     pub fn new_with_environment(enclosing: &Rc<RefCell<Environment>>) -> Self {
         Self {
             values: HashMap::new(),
+            slots: Vec::new(),
             enclosing: Some(Rc::clone(enclosing)),
         }
     }
@@ -29,11 +36,9 @@ impl Environment {
         depth: usize,
     ) -> Option<Rc<RefCell<Environment>>> {
         let mut current_env = environment.clone();
-        let mut i = 0;
-        while i < depth && current_env.borrow().enclosing.is_some() {
-            let temp = current_env.borrow().enclosing.clone().unwrap();
-            current_env = temp;
-            i += 1;
+        for _ in 0..depth {
+            let enclosing = current_env.borrow().enclosing.clone()?;
+            current_env = enclosing;
         }
         Some(current_env)
     }
@@ -47,35 +52,34 @@ impl Environment {
         Some(current_env)
     }
 
+    /// Defines a variable in this scope. Local variables take the next slot,
+    /// which matches the slot the resolver gave them.
     pub fn define(&mut self, name: String, value: Literal) {
-        self.values.insert(name, value);
+        if self.enclosing.is_none() {
+            self.values.insert(name, value);
+        } else {
+            self.slots.push(value);
+        }
     }
 
-    pub fn get(&self, name: String) -> Option<Literal> {
-        match self.values.get(&name) {
-            Some(variable) => Some(variable.clone()),
-            None => self.enclosing.as_ref().map_or_else(
-                || {
-                    println!("could not find in get for {}", name);
-                    None
-                },
-                |enclosing| enclosing.borrow().get(name.clone()),
-            ),
-        }
+    pub fn get(&self, name: &str) -> Option<Literal> {
+        self.values.get(name).cloned()
     }
 
-    pub fn assign(&mut self, name: String, value: Literal) -> Option<Literal> {
-        if self.values.contains_key(&name) {
-            return self.values.insert(name.clone(), value.clone());
-        } else {
-            match &mut self.enclosing {
-                None => {
-                    println!("could not find in assign for {}", name);
-                    return None;
-                }
-                Some(enclosing) => return enclosing.borrow_mut().assign(name, value),
-            }
-        }
+    pub fn get_at(&self, slot: usize) -> Option<Literal> {
+        self.slots.get(slot).cloned()
+    }
+
+    pub fn assign(&mut self, name: &str, value: Literal) -> Option<Literal> {
+        self.values
+            .get_mut(name)
+            .map(|variable| std::mem::replace(variable, value))
+    }
+
+    pub fn assign_at(&mut self, slot: usize, value: Literal) -> Option<Literal> {
+        self.slots
+            .get_mut(slot)
+            .map(|variable| std::mem::replace(variable, value))
     }
 
     #[allow(dead_code)]
@@ -84,6 +88,9 @@ impl Environment {
         for (key, value) in &self.values {
             println!("{} = {}", key, value);
         }
+        for (slot, value) in self.slots.iter().enumerate() {
+            println!("#{} = {}", slot, value);
+        }
         println!("---------- Printing Values ----------");
         let mut temp_env = self.enclosing.clone();
         println!("---------- Printing Enclosed ----------");
@@ -91,6 +98,9 @@ impl Environment {
             for (key, value) in temp_env.clone().unwrap().borrow().values.clone() {
                 println!("{} = {}", key, value);
             }
+            for (slot, value) in temp_env.clone().unwrap().borrow().slots.iter().enumerate() {
+                println!("#{} = {}", slot, value);
+            }
             temp_env = temp_env
                 .clone()
                 .as_ref()
@@ -103,3 +113,29 @@ impl Environment {
         println!("\n");
     }
 }
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Trace for RefCell<Environment> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(environment) = self.try_borrow() {
+            for value in environment.values.values().chain(&environment.slots) {
+                value.trace(tracer);
+            }
+            if let Some(enclosing) = &environment.enclosing {
+                tracer.edge(enclosing);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut environment) = self.try_borrow_mut() {
+            environment.values.clear();
+            environment.slots.clear();
+        }
+    }
+}
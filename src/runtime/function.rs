@@ -1,46 +1,91 @@
-use super::{Environment, Function, FunctionInfo, Interpreter, Literal, LoxCallable, Result};
+use super::{
+    Arity, Environment, Function, FunctionInfo, Interpreter, Literal, LoxCallable, LoxInstance,
+    RuntimeResult, StatementResult,
+};
+use crate::gc::{Trace, Tracer};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+/// The stack a call needs left to start on the current stack. Each Lox call
+/// recurses on the Rust stack, so deeper calls go on to new segments of
+/// `STACK_SEGMENT` bytes instead of overflowing the stack of the host.
+const RED_ZONE: usize = 1024 * 1024;
+const STACK_SEGMENT: usize = 16 * 1024 * 1024;
+
 #[derive(Debug)]
 pub struct LoxFunction {
     function: Function,
     closure: Rc<RefCell<Environment>>,
+    is_initializer: bool,
 }
 
 impl LoxFunction {
-    pub fn new(function: Function, closure: Rc<RefCell<Environment>>) -> Self {
-        Self { function, closure }
+    pub fn new(
+        function: Function,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        Self {
+            function,
+            closure,
+            is_initializer,
+        }
     }
-}
 
-impl LoxCallable for LoxFunction {
-    fn call(&self, interpreter: &mut Interpreter, args: Vec<Literal>) -> Result<Literal> {
+    /// Returns a copy of this method whose closure defines `this` as the given
+    /// instance, in the first slot of a scope of its own.
+    pub fn bind(&self, instance: Rc<LoxInstance>) -> LoxFunction {
         let environment = Rc::new(RefCell::new(Environment::new_with_environment(
             &self.closure,
         )));
+        environment
+            .borrow_mut()
+            .define("this".to_string(), Literal::Instance(instance));
+        LoxFunction::new(self.function.clone(), environment, self.is_initializer)
+    }
+
+    fn bound_this(&self) -> Literal {
+        self.closure.borrow().get_at(0).unwrap_or(Literal::Nil)
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Literal>,
+    ) -> RuntimeResult<Literal> {
+        interpreter.enter_call()?;
+        let environment = interpreter.new_environment(&self.closure);
 
         for (parameter, value) in self.function.parameters().iter().zip(args.iter()) {
             environment
                 .borrow_mut()
                 .define(parameter.get_name().clone(), value.clone())
         }
-        let result = interpreter.execute_block(self.function.body(), environment);
+        let result = stacker::maybe_grow(RED_ZONE, STACK_SEGMENT, || {
+            interpreter.execute_block(self.function.body(), environment)
+        })?;
+        if self.is_initializer {
+            return Ok(self.bound_this());
+        }
         match result {
-            Ok(return_value) => match return_value {
-                Some(literal) => return Ok(literal),
-                _ => {}
-            },
-            _ => {}
+            StatementResult::Return(value) => Ok(value),
+            _ => Ok(Literal::Nil),
         }
-        Ok(Literal::Nil)
     }
 
-    fn arity(&self) -> usize {
-        self.function.parameters().len()
+    fn arity(&self) -> Arity {
+        Arity::Exact(self.function.parameters().len())
     }
 
     fn name(&self) -> String {
         self.function.identifier()
     }
 }
+
+impl Trace for LoxFunction {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.closure);
+    }
+}
@@ -1,20 +1,25 @@
 use crate::ast::expression::Identifier;
+use crate::gc::{Trace, Tracer};
 
+use super::native::HostData;
 use super::{Literal, LoxClass};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct LoxInstance {
-    class: LoxClass,
+    class: Rc<LoxClass>,
     fields: RefCell<HashMap<String, Literal>>,
+    host_data: HostData,
 }
 
 impl LoxInstance {
-    pub fn new(class: LoxClass) -> Self {
+    pub fn new(class: Rc<LoxClass>) -> Self {
         Self {
             class,
             fields: RefCell::new(HashMap::new()),
+            host_data: RefCell::new(None),
         }
     }
 
@@ -22,11 +27,49 @@ impl LoxInstance {
         self.class.to_string()
     }
 
-    pub fn get(&self, name: &Identifier) -> Option<Literal> {
-        self.fields.borrow().get(&name.get_name()).cloned()
+    pub fn class(&self) -> &Rc<LoxClass> {
+        &self.class
+    }
+
+    /// Fields shadow methods, so a method is only bound to the instance when no
+    /// field with the same name exists.
+    pub fn get(instance: &Rc<LoxInstance>, name: &Identifier) -> Option<Literal> {
+        if let Some(field) = instance.fields.borrow().get(&name.get_name()) {
+            return Some(field.clone());
+        }
+        instance
+            .class
+            .find_method(&name.get_name())
+            .map(|method| Literal::Callable(method.bind(instance.clone())))
+    }
+
+    /// What the native class this is an instance of attached to it.
+    pub fn host_data(&self) -> &HostData {
+        &self.host_data
+    }
+
+    pub fn field(&self, name: &str) -> Option<Literal> {
+        self.fields.borrow().get(name).cloned()
     }
 
     pub fn set(&self, field: String, value: Literal) {
         self.fields.borrow_mut().insert(field, value);
     }
 }
+
+impl Trace for LoxInstance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.class);
+        if let Ok(fields) = self.fields.try_borrow() {
+            for value in fields.values() {
+                value.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
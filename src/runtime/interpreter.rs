@@ -1,42 +1,249 @@
+use super::builtins::SharedOutput;
 use super::Environment;
-use super::Error;
 use super::Literal;
-use super::Result;
+use super::RuntimeError;
+use super::RuntimeResult;
 use super::*;
+use crate::gc::{GcStats, Heap, Trace};
+use crate::module::{Import, Modules};
+use crate::sandbox::{Budget, Sandbox};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
 use std::rc::Rc;
 
 pub struct Interpreter {
     pub environment: Rc<RefCell<Environment>>,
+    frames: Vec<CallFrame>,
+    output: Box<dyn Write>,
+    /// Where `eprint` writes.
+    error_output: SharedOutput,
+    heap: Heap,
+    /// The global scope of every module imported so far.
+    modules: Modules<Rc<RefCell<Environment>>>,
+    /// The globals defined by the host, which every module starts with.
+    prelude: HashMap<String, Literal>,
+    /// The class of the values `catch` clauses receive for runtime errors.
+    error_class: Rc<LoxClass>,
+    /// The value of the `throw` statement whose error is unwinding, until a
+    /// `catch` clause receives it.
+    thrown: Option<Literal>,
+    /// What is left of the limits of the sandbox in the current run.
+    budget: Budget,
 }
 
-type StatementResult = Option<Literal>;
+/// A function call that is currently executing, along with the line of the
+/// call site it was invoked from and the span of the call expression.
+struct CallFrame {
+    function: String,
+    line: usize,
+    span: Span,
+}
+
+/// How control leaves a statement: normally, or by unwinding to the enclosing
+/// loop or function.
+#[derive(Debug)]
+pub enum StatementResult {
+    Normal,
+    Return(Literal),
+    Break,
+    Continue,
+}
 
 impl Interpreter {
     pub fn new() -> Self {
-        let environment = Rc::new(RefCell::new(Environment::new()));
-        environment
-            .borrow_mut()
-            .define(Clock.name().into(), Literal::Callable(Rc::new(Clock)));
-        Self { environment }
+        Self::sandboxed(&Sandbox::unrestricted())
+    }
+
+    /// An interpreter with only the native modules `sandbox` allows, running
+    /// programs within its limits.
+    pub fn sandboxed(sandbox: &Sandbox) -> Self {
+        let error_class = Rc::new(LoxClass::new(String::from("Error"), None, HashMap::new()));
+        let error_output: SharedOutput = Rc::new(RefCell::new(Box::new(io::stderr())));
+        let mut interpreter = Self {
+            environment: Rc::new(RefCell::new(Environment::new())),
+            frames: vec![],
+            output: Box::new(io::stdout()),
+            error_output: error_output.clone(),
+            heap: Heap::new(),
+            modules: Modules::new(),
+            prelude: HashMap::new(),
+            error_class: error_class.clone(),
+            thrown: None,
+            budget: sandbox.budget(),
+        };
+        let modules = [
+            builtins::core(),
+            builtins::time(),
+            builtins::math(),
+            builtins::string(),
+            builtins::io(error_output),
+        ];
+        for module in modules {
+            if sandbox.allows_module(module.name()) {
+                interpreter.define_module(module);
+            }
+        }
+        if sandbox.allows_module("core") {
+            interpreter.set_global("gc", Literal::Callable(Rc::new(builtins::Gc)));
+        }
+        if !sandbox.allows_imports() {
+            interpreter.modules.forbid_imports();
+        }
+        interpreter.set_global("Error", Literal::Class(error_class));
+        interpreter
+    }
+
+    /// Defines a global function implemented by a Rust closure.
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: impl Into<Arity>,
+        function: impl Fn(&Args) -> NativeResult<Literal> + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.set_global(name, Literal::Callable(Rc::new(native)));
+    }
+
+    /// Defines every function and constant of `module` as a global.
+    pub fn define_module(&mut self, module: NativeModule) {
+        let (functions, constants) = module.into_parts();
+        for function in functions {
+            self.set_global(&function.name(), Literal::Callable(Rc::new(function)));
+        }
+        for (name, value) in constants {
+            self.set_global(&name, value);
+        }
+    }
+
+    /// Defines a global class whose methods are implemented in Rust.
+    pub fn define_class(&mut self, class: NativeClass) {
+        let name = class.name().to_string();
+        self.set_global(&name, Literal::Class(Rc::new(LoxClass::native(class))));
+    }
+
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Sends what `eprint` writes to `output` instead of stderr.
+    pub fn set_error_output(&mut self, output: Box<dyn Write>) {
+        *self.error_output.borrow_mut() = output;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Literal> {
+        Environment::get_global(&self.environment).and_then(|env| env.borrow().get(name))
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Literal) {
+        self.prelude.insert(name.to_string(), value.clone());
+        if let Some(env) = Environment::get_global(&self.environment) {
+            env.borrow_mut().define(name.to_string(), value);
+        }
+    }
+
+    pub fn modules(&mut self) -> &mut Modules<Rc<RefCell<Environment>>> {
+        &mut self.modules
+    }
+
+    /// Lets the collector free `object` once it is only reachable through
+    /// cycles. Every environment, instance, list and map the program creates
+    /// must be tracked, as those are the objects cycles are made of.
+    pub fn track<T: Trace + 'static>(&mut self, object: &Rc<T>) {
+        self.heap.track(object);
+    }
+
+    /// Tracks a value created outside of the interpreter, such as the result
+    /// of a native function.
+    pub fn track_value(&mut self, value: &Literal) {
+        match value {
+            Literal::Instance(instance) => self.track(instance),
+            Literal::List(list) => self.track(list),
+            Literal::Map(map) => self.track(map),
+            _ => {}
+        }
+    }
+
+    /// Frees the objects only reachable through cycles and returns how many
+    /// there were.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.collect()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Runs a resolved program and returns the value it produces, which is the
+    /// value of its final statement when that is an expression statement.
+    pub fn interpret(
+        &mut self,
+        statements: &[Statement],
+        run_in_repl: bool,
+    ) -> RuntimeResult<Literal> {
+        self.budget.start();
+        // Only the value of the final statement is kept, so that earlier ones
+        // don't keep what they refer to alive.
+        let (last, rest) = match statements.split_last() {
+            Some(split) => split,
+            None => return Ok(Literal::Nil),
+        };
+        let result = rest
+            .iter()
+            .try_for_each(|statement| self.interpret_statement(statement, run_in_repl).map(drop))
+            .and_then(|_| self.interpret_statement(last, run_in_repl));
+        if result.is_err() {
+            self.thrown = None;
+        }
+        result
+    }
+
+    fn interpret_statement(
+        &mut self,
+        statement: &Statement,
+        run_in_repl: bool,
+    ) -> RuntimeResult<Literal> {
+        match statement {
+            Statement::ExpressionStatement(expr) => {
+                let value = self.evaluate(expr)?;
+                if run_in_repl {
+                    self.print(&value, expr.span())?;
+                }
+                Ok(value)
+            }
+            _ => {
+                self.evaluate_statement(statement, run_in_repl)?;
+                Ok(Literal::Nil)
+            }
+        }
+    }
+
+    fn print(&mut self, value: &Literal, span: Span) -> RuntimeResult<()> {
+        writeln!(self.output, "{}", value)
+            .map_err(|_| RuntimeError::new(span, String::from("Can't write to the output.")))
     }
 
     pub fn evaluate_statement(
         &mut self,
         statement: &Statement,
         run_in_repl: bool,
-    ) -> Result<StatementResult> {
+    ) -> RuntimeResult<StatementResult> {
         match statement {
-            Statement::ReturnStatement(return_expr) => match return_expr {
+            Statement::ReturnStatement(_keyword, return_expr) => match return_expr {
                 Some(return_expr) => match self.evaluate(&*return_expr) {
-                    Ok(return_value) => Ok(Some(return_value)),
+                    Ok(return_value) => Ok(StatementResult::Return(return_value)),
                     Err(e) => Err(e),
                 },
-                None => Ok(None),
+                None => Ok(StatementResult::Return(Literal::Nil)),
             },
+            Statement::BreakStatement(_keyword) => Ok(StatementResult::Break),
+            Statement::ContinueStatement(_keyword) => Ok(StatementResult::Continue),
             Statement::PrintStatement(print_expr) => {
-                println!("{}", self.evaluate(&*print_expr)?);
-                Ok(None)
+                let value = self.evaluate(&*print_expr)?;
+                self.print(&value, print_expr.span())?;
+                Ok(StatementResult::Normal)
             }
             Statement::IfStatement(condition, then_branch, else_branch) => {
                 let condition_expr = self.evaluate(&*condition)?;
@@ -45,142 +252,321 @@ impl Interpreter {
                 } else {
                     return match else_branch {
                         Some(else_expr) => self.evaluate_statement(&*else_expr, run_in_repl),
-                        None => return Ok(None),
+                        None => return Ok(StatementResult::Normal),
                     };
                 }
             }
-            Statement::WhileStatement(condition, body) => {
+            Statement::WhileStatement(condition, body, increment) => {
                 let mut condition_expr = self.evaluate(&*condition)?;
                 while Literal::is_truthy(&condition_expr) {
-                    self.evaluate_statement(body, run_in_repl)?;
+                    match self.evaluate_statement(body, run_in_repl)? {
+                        StatementResult::Break => break,
+                        StatementResult::Return(value) => {
+                            return Ok(StatementResult::Return(value))
+                        }
+                        StatementResult::Normal | StatementResult::Continue => {}
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(&*increment)?;
+                    }
                     condition_expr = self.evaluate(&*condition)?;
                 }
-                Ok(None)
+                Ok(StatementResult::Normal)
             }
             Statement::ExpressionStatement(expr) => {
-                self.evaluate(&*expr)?;
-                Ok(None)
+                let value = self.evaluate(&*expr)?;
+                if run_in_repl {
+                    self.print(&value, expr.span())?;
+                }
+                Ok(StatementResult::Normal)
             }
             Statement::VariableDeclaration(id, init_expr) => match init_expr {
                 Some(expr) => {
                     let value = self.evaluate(&*expr)?;
                     self.environment.borrow_mut().define(id.get_name(), value);
-                    Ok(None)
+                    Ok(StatementResult::Normal)
                 }
                 None => {
                     self.environment
                         .borrow_mut()
                         .define(id.get_name(), Literal::Nil);
-                    Ok(None)
+                    Ok(StatementResult::Normal)
                 }
             },
             Statement::FunctionDeclaration(func) => {
-                let closure = Rc::new(RefCell::new(Environment::new_with_environment(
-                    &self.environment,
-                )));
-                let f = LoxFunction::new(Function::Declaration(func.clone()), closure);
+                let f = LoxFunction::new(
+                    Function::Declaration(func.clone()),
+                    self.environment.clone(),
+                    false,
+                );
                 self.environment
                     .borrow_mut()
                     .define(f.name().into(), Literal::Callable(Rc::new(f)));
-                Ok(None)
+                Ok(StatementResult::Normal)
             }
-            Statement::ClassDeclaration(id, _methods) => {
-                self.environment
-                    .borrow_mut()
-                    .define(id.get_name(), Literal::Nil);
+            Statement::ClassDeclaration(id, superclass, methods) => {
+                let superclass = match superclass {
+                    Some(superclass_var) => match self.lookup(superclass_var)? {
+                        Literal::Class(class) => Some(class),
+                        _ => {
+                            return Err(RuntimeError::new(
+                                superclass_var.get_identifier().get_span(),
+                                String::from("Superclass must be a class."),
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+
+                let enclosing = self.environment.clone();
+                if let Some(superclass) = &superclass {
+                    self.environment = self.new_environment(&enclosing);
+                    self.environment
+                        .borrow_mut()
+                        .define("super".to_string(), Literal::Class(superclass.clone()));
+                }
 
-                let class = Rc::new(LoxClass::new(id.get_name()));
+                let mut class_methods = HashMap::new();
+                for method in methods {
+                    if let Statement::FunctionDeclaration(func) = &**method {
+                        let function = LoxFunction::new(
+                            Function::Declaration(func.clone()),
+                            self.environment.clone(),
+                            func.get_identifier() == "init",
+                        );
+                        class_methods.insert(func.get_identifier(), Rc::new(function));
+                    }
+                }
+
+                let class = Rc::new(LoxClass::new(id.get_name(), superclass, class_methods));
+                self.environment = enclosing;
+                // Methods look the class up when they run, so defining it after
+                // they were created is enough for them to refer to it.
                 self.environment
                     .borrow_mut()
-                    .assign(id.get_name(), Literal::Class(class));
-                Ok(None)
+                    .define(id.get_name(), Literal::Class(class));
+                Ok(StatementResult::Normal)
             }
             Statement::BlockStatement(statements) => {
-                match self.execute_block(
-                    statements,
-                    Rc::new(RefCell::new(Environment::new_with_environment(
-                        &self.environment,
-                    ))),
-                ) {
-                    Ok(block) => Ok(block),
-                    Err(_) => Ok(None),
+                let environment = self.new_environment(&self.environment.clone());
+                self.execute_block(statements, environment)
+            }
+            Statement::ImportStatement(_keyword, path, name) => {
+                self.import(path, name.as_ref())?;
+                Ok(StatementResult::Normal)
+            }
+            Statement::ThrowStatement(_keyword, value) => {
+                let span = value.span();
+                let value = self.evaluate(value)?;
+                Err(self.throw(value, span))
+            }
+            Statement::TryStatement(_keyword, body, catch, finally) => {
+                let environment = self.new_environment(&self.environment.clone());
+                let mut result = self.execute_block(body, environment);
+                if let Some((name, handler)) = catch {
+                    result = match result {
+                        Err(err) if err.limit.is_none() => {
+                            let value = self.caught(err);
+                            let environment = self.new_environment(&self.environment.clone());
+                            environment.borrow_mut().define(name.get_name(), value);
+                            self.execute_block(handler, environment)
+                        }
+                        result => result,
+                    };
+                }
+                // Running out of a limit of the sandbox stops the program
+                // without running anything more.
+                if let Err(RuntimeError { limit: Some(_), .. }) = result {
+                    return result;
+                }
+                if let Some(finally) = finally {
+                    // The `finally` block may throw and catch values of its
+                    // own, which mustn't replace the one still unwinding.
+                    let thrown = self.thrown.take();
+                    let environment = self.new_environment(&self.environment.clone());
+                    match self.execute_block(finally, environment)? {
+                        StatementResult::Normal => self.thrown = thrown,
+                        // Leaving the block by `break`, `continue` or `return`
+                        // overrides how the `try` statement was left.
+                        left => return Ok(left),
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    /// Raises the error unwinding from a `throw` statement. Instances with a
+    /// `message`, such as caught runtime errors, are reported by it if no
+    /// `catch` clause receives them.
+    fn throw(&mut self, value: Literal, span: Span) -> RuntimeError {
+        let message = match &value {
+            Literal::Instance(instance) => match instance.field("message") {
+                Some(Literal::String(message)) => Some(message),
+                _ => None,
+            },
+            _ => None,
+        };
+        let message = message.unwrap_or_else(|| format!("Uncaught exception: {}.", value));
+        let mut err = RuntimeError::new(span, message);
+        err.thrown = true;
+        self.thrown = Some(value);
+        err
+    }
+
+    /// The value a `catch` clause receives for `err`: the value thrown, or an
+    /// `Error` instance with the message and line of a runtime error.
+    fn caught(&mut self, err: RuntimeError) -> Literal {
+        if err.thrown {
+            if let Some(value) = self.thrown.take() {
+                return value;
+            }
+        }
+        let error = Rc::new(LoxInstance::new(self.error_class.clone()));
+        error.set(String::from("message"), Literal::String(err.message));
+        error.set(String::from("line"), Literal::Number(err.line as f64));
+        self.track(&error);
+        Literal::Instance(error)
+    }
+
+    /// Runs the module `path` names unless it ran before, then defines the
+    /// global `name` it defines, or all of its globals but the prelude.
+    fn import(&mut self, path: &Token, name: Option<&Identifier>) -> RuntimeResult<()> {
+        let import = match &path.literal {
+            Some(Literal::String(import)) => import.clone(),
+            _ => path.lexeme.clone(),
+        };
+        let module = match self.modules.begin(&import, path.span)? {
+            Import::Cached(module) => module,
+            Import::Load(statements) => {
+                let module = Rc::new(RefCell::new(Environment::new()));
+                module.borrow_mut().values = self.prelude.clone();
+                self.track(&module);
+                let previous = mem::replace(&mut self.environment, module.clone());
+                let result = statements
+                    .iter()
+                    .try_for_each(|statement| self.evaluate_statement(statement, false).map(drop));
+                self.environment = previous;
+                if let Err(err) = result {
+                    self.modules.cancel();
+                    return Err(err);
+                }
+                self.modules.finish(module.clone());
+                module
+            }
+        };
+
+        let module = module.borrow();
+        let mut globals = self.environment.borrow_mut();
+        match name {
+            Some(name) => {
+                let value = module.get(&name.get_name()).ok_or_else(|| {
+                    RuntimeError::new(
+                        name.get_span(),
+                        format!("Module '{}' doesn't define '{}'.", import, name.get_name()),
+                    )
+                })?;
+                globals.define(name.get_name(), value);
+            }
+            None => {
+                for (name, value) in &module.values {
+                    if !self.prelude.contains_key(name) {
+                        globals.define(name.clone(), value.clone());
+                    }
                 }
             }
         }
+        Ok(())
     }
 
     pub fn execute_block(
         &mut self,
         statements: &Vec<Statement>,
         environment: Rc<RefCell<Environment>>,
-    ) -> Result<StatementResult> {
+    ) -> RuntimeResult<StatementResult> {
         let previous = self.environment.clone();
         self.environment = environment;
-        let mut return_result = Ok(None);
+        let mut return_result = Ok(StatementResult::Normal);
         for statement in statements {
             match self.evaluate_statement(&statement, false) {
-                Ok(return_value) => match return_value {
-                    Some(literal) => {
-                        return_result = Ok(Some(literal));
-                        break;
-                    }
-                    _ => {}
-                },
-                _ => {}
+                Ok(StatementResult::Normal) => {}
+                result => {
+                    return_result = result;
+                    break;
+                }
             }
         }
+        // The enclosing environment is restored even when a runtime error unwinds
+        // through the block, so the interpreter stays usable afterwards.
         self.environment = previous;
         return_result
     }
 
-    pub fn evaluate(&mut self, expr_node: &ExpressionNode) -> Result<Literal> {
-        let line = expr_node.line();
+    pub fn evaluate(&mut self, expr_node: &ExpressionNode) -> RuntimeResult<Literal> {
+        let span = expr_node.span();
+        if let Err(limit) = self.budget.step(&mut self.heap) {
+            return Err(self.budget.error(limit, span));
+        }
         match expr_node.expr() {
             Expression::FunctionExpression(func) => {
-                let callable =
-                    LoxFunction::new(Function::Expression(func.clone()), self.environment.clone());
+                let callable = LoxFunction::new(
+                    Function::Expression(func.clone()),
+                    self.environment.clone(),
+                    false,
+                );
                 Ok(Literal::Callable(Rc::new(callable)))
             }
-            Expression::CallExpression(callee, _token, args) => {
+            Expression::CallExpression(callee, token, args) => {
                 let callee_expr = self.evaluate(&*callee)?;
-                let args_expr: Vec<Literal> = args
+                let args_expr = args
                     .iter()
-                    .map(|arg_expr| self.evaluate(&arg_expr).unwrap())
-                    .collect();
+                    .map(|arg_expr| self.evaluate(&arg_expr))
+                    .collect::<RuntimeResult<Vec<Literal>>>()?;
 
                 match callee_expr.into_callable() {
                     Some(function) => {
-                        if args.len() != function.arity() {
-                            return Err(Error {
-                                line: line.into(),
-                                message: format!(
+                        if !function.arity().accepts(args.len()) {
+                            return Err(RuntimeError::new(
+                                span,
+                                format!(
                                     "Expected {} arguments but got {}.",
                                     function.arity(),
                                     args.len()
                                 ),
-                            });
+                            ));
                         }
-                        function.call(self, args_expr)
+                        self.frames.push(CallFrame {
+                            function: function.name(),
+                            line: token.line,
+                            span,
+                        });
+                        let mut result = function.call(self, args_expr);
+                        if let Err(err) = &mut result {
+                            if err.trace.is_empty() {
+                                err.trace = self.stack_trace(err.line);
+                            }
+                        }
+                        self.frames.pop();
+                        result
                     }
-                    None => Err(Error {
-                        line: line.into(),
-                        message: String::from("Can only call functions and classes."),
-                    }),
+                    None => Err(RuntimeError::new(
+                        span,
+                        String::from("Can only call functions and classes."),
+                    )),
                 }
             }
             Expression::GetExpression(name, expr) => {
                 let object = self.evaluate(expr)?;
                 match object {
                     Literal::Instance(instance) => {
-                        return instance.get(name).ok_or_else(|| Error {
-                            line: line.into(),
-                            message: String::from("Getter not found on instance."),
+                        return LoxInstance::get(&instance, name).ok_or_else(|| {
+                            RuntimeError::new(span, String::from("Getter not found on instance."))
                         })
                     }
-                    _ => Err(Error {
-                        line: line.into(),
-                        message: String::from("Only instances have properties."),
-                    }),
+                    _ => Err(RuntimeError::new(
+                        span,
+                        String::from("Only instances have properties."),
+                    )),
                 }
             }
             Expression::SetExpression(object_expr, name, value_expr) => {
@@ -188,128 +574,256 @@ impl Interpreter {
                 match object {
                     Literal::Instance(instance) => {
                         let value = self.evaluate(value_expr)?;
-                        instance.set(name.get_name(), value);
-                        return instance.get(name).ok_or_else(|| Error {
-                            line: line.into(),
-                            message: String::from("Getter not found on instance."),
-                        });
+                        instance.set(name.get_name(), value.clone());
+                        Ok(value)
+                    }
+                    _ => Err(RuntimeError::new(
+                        span,
+                        String::from("Only instances have properties."),
+                    )),
+                }
+            }
+            Expression::Super(keyword, method) => {
+                // `this` is the only variable of the scope right inside the one
+                // defining `super`.
+                let depth = keyword.get_depth().unwrap_or(0);
+                let superclass = self.lookup(keyword)?;
+                let object = Environment::ancestor(&self.environment, depth.saturating_sub(1))
+                    .and_then(|env| env.borrow().get_at(0));
+                match (superclass, object) {
+                    (Literal::Class(superclass), Some(Literal::Instance(instance))) => superclass
+                        .find_method(&method.get_name())
+                        .map(|method| Literal::Callable(method.bind(instance)))
+                        .ok_or_else(|| {
+                            RuntimeError::new(
+                                span,
+                                format!("Undefined property '{}'.", method.get_name()),
+                            )
+                        }),
+                    _ => Err(RuntimeError::new(
+                        span,
+                        String::from("Can't use 'super' outside of a class."),
+                    )),
+                }
+            }
+            Expression::ListExpression(elements) => {
+                let values = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<RuntimeResult<Vec<Literal>>>()?;
+                let list = Rc::new(RefCell::new(values));
+                self.track(&list);
+                Ok(Literal::List(list))
+            }
+            Expression::MapExpression(entries) => {
+                let mut map = LoxMap::new();
+                for (key_expr, value_expr) in entries {
+                    let key = self.evaluate(key_expr)?;
+                    let key = MapKey::from_literal(&key, key_expr.span())?;
+                    let value = self.evaluate(value_expr)?;
+                    map.insert(key, value);
+                }
+                let map = Rc::new(RefCell::new(map));
+                self.track(&map);
+                Ok(Literal::Map(map))
+            }
+            Expression::IndexGetExpression(object_expr, _bracket, index_expr) => {
+                let object = self.evaluate(object_expr)?;
+                let index = self.evaluate(index_expr)?;
+                match object {
+                    Literal::List(list) => {
+                        let list = list.borrow();
+                        let position = list::element_index(index.as_number(), list.len(), span)?;
+                        Ok(list[position].clone())
+                    }
+                    Literal::Map(map) => {
+                        let key = MapKey::from_literal(&index, span)?;
+                        map.borrow().get(&key).ok_or_else(|| {
+                            RuntimeError::new(span, format!("Key {} not found in map.", index))
+                        })
+                    }
+                    _ => Err(RuntimeError::new(
+                        span,
+                        String::from("Only lists and maps can be indexed."),
+                    )),
+                }
+            }
+            Expression::IndexSetExpression(object_expr, _bracket, index_expr, value_expr) => {
+                let object = self.evaluate(object_expr)?;
+                let index = self.evaluate(index_expr)?;
+                let value = self.evaluate(value_expr)?;
+                match object {
+                    Literal::List(list) => {
+                        let mut list = list.borrow_mut();
+                        let position = list::element_index(index.as_number(), list.len(), span)?;
+                        list[position] = value.clone();
+                        Ok(value)
+                    }
+                    Literal::Map(map) => {
+                        let key = MapKey::from_literal(&index, span)?;
+                        let mut map = map.borrow_mut();
+                        if !map.contains_key(&key) {
+                            self.check_length(map.len() + 1, span)?;
+                        }
+                        map.insert(key, value.clone());
+                        Ok(value)
+                    }
+                    _ => Err(RuntimeError::new(
+                        span,
+                        String::from("Only lists and maps can be indexed."),
+                    )),
+                }
+            }
+            Expression::SliceExpression(object_expr, _bracket, start_expr, end_expr) => {
+                let object = self.evaluate(object_expr)?;
+                let start = match start_expr {
+                    Some(start_expr) => Some(self.evaluate(start_expr)?),
+                    None => None,
+                };
+                let end = match end_expr {
+                    Some(end_expr) => Some(self.evaluate(end_expr)?),
+                    None => None,
+                };
+                match object {
+                    Literal::List(list) => {
+                        let list = list.borrow();
+                        let start = match start {
+                            Some(start) => {
+                                list::boundary_index(start.as_number(), list.len(), span)?
+                            }
+                            None => 0,
+                        };
+                        let end = match end {
+                            Some(end) => list::boundary_index(end.as_number(), list.len(), span)?,
+                            None => list.len(),
+                        };
+                        if start > end {
+                            return Err(RuntimeError::new(
+                                span,
+                                format!("Slice start {} is after slice end {}.", start, end),
+                            ));
+                        }
+                        let slice = Rc::new(RefCell::new(list[start..end].to_vec()));
+                        self.track(&slice);
+                        Ok(Literal::List(slice))
                     }
-                    _ => Err(Error {
-                        line: line.into(),
-                        message: String::from("Only instances have properties."),
-                    }),
+                    _ => Err(RuntimeError::new(
+                        span,
+                        String::from("Only lists can be sliced."),
+                    )),
                 }
             }
             Expression::Literal(val) => Ok(val.clone()),
             Expression::Grouping(group_expr) => return self.evaluate(&*group_expr),
             Expression::Unary(unary_op, unary_expr) => {
-                let line = unary_expr.line();
                 let value = self.evaluate(&*unary_expr)?;
                 match unary_op {
                     UnaryOperator::MINUS => {
                         if let Literal::Number(n) = value {
                             Ok(Literal::Number(-n))
                         } else {
-                            Err(Error {
-                                line: line.into(),
-                                message: String::from("Operand must be a number."),
-                            })
+                            Err(RuntimeError::new(
+                                span,
+                                String::from("Operand must be a number."),
+                            ))
                         }
                     }
                     UnaryOperator::BANG => {
                         if let Literal::Boolean(b) = value {
                             Ok(Literal::Boolean(!b))
                         } else {
-                            Err(Error {
-                                line: line.into(),
-                                message: String::from("Operand must be a boolean."),
-                            })
+                            Err(RuntimeError::new(
+                                span,
+                                String::from("Operand must be a boolean."),
+                            ))
                         }
                     }
                 }
             }
             Expression::BinaryExpression(left_expr, bin_op, right_expr) => {
-                let line = left_expr.line();
                 let left = self.evaluate(&*left_expr)?;
                 let right = self.evaluate(&*right_expr)?;
                 match bin_op {
                     BinaryOperator::PLUS => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => Ok(Literal::Number(n1 + n2)),
                         (Literal::String(s1), Literal::String(s2)) => {
+                            self.check_length(s1.len() + s2.len(), span)?;
                             Ok(Literal::String(format!("{}{}", s1, s2)))
                         }
                         (Literal::Number(n), Literal::String(s)) => {
+                            self.check_length(s.len(), span)?;
                             Ok(Literal::String(format!("{}{}", n, s)))
                         }
                         (Literal::String(s), Literal::Number(n)) => {
+                            self.check_length(s.len(), span)?;
                             Ok(Literal::String(format!("{}{}", s, n)))
                         }
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers or strings."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers or strings."),
+                        )),
                     },
                     BinaryOperator::MINUS => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => Ok(Literal::Number(n1 - n2)),
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::SLASH => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => {
                             if n2 == 0.0 {
-                                return Err(Error {
-                                    line: line.into(),
-                                    message: String::from("Cannot divide by zero."),
-                                });
+                                return Err(RuntimeError::new(
+                                    span,
+                                    String::from("Cannot divide by zero."),
+                                ));
                             } else {
                                 return Ok(Literal::Number(n1 / n2));
                             };
                         }
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::STAR => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => Ok(Literal::Number(n1 * n2)),
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::GREATER => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => Ok(Literal::Boolean(n1 > n2)),
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::GREATEREQUAL => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => {
                             Ok(Literal::Boolean(n1 >= n2))
                         }
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::LESS => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => Ok(Literal::Boolean(n1 < n2)),
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::LESSEQUAL => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => {
                             Ok(Literal::Boolean(n1 <= n2))
                         }
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be numbers."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be numbers."),
+                        )),
                     },
                     BinaryOperator::BANGEQUAL => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => {
@@ -321,10 +835,10 @@ impl Interpreter {
                         (Literal::String(s1), Literal::String(s2)) => {
                             Ok(Literal::Boolean(s1 != s2))
                         }
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be strings, numbers or booleans."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be strings, numbers or booleans."),
+                        )),
                     },
                     BinaryOperator::EQUALEQUAL => match (left, right) {
                         (Literal::Number(n1), Literal::Number(n2)) => {
@@ -336,24 +850,23 @@ impl Interpreter {
                         (Literal::String(s1), Literal::String(s2)) => {
                             Ok(Literal::Boolean(s1 == s2))
                         }
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be strings, numbers or booleans."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be strings, numbers or booleans."),
+                        )),
                     },
                     BinaryOperator::COMMA => match (left, right) {
                         (_, Literal::Number(n2)) => Ok(Literal::Number(n2)),
                         (_, Literal::String(s2)) => Ok(Literal::String(s2)),
                         (_, Literal::Boolean(b2)) => Ok(Literal::Boolean(b2)),
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Operands must be strings, numbers or booleans."),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            span,
+                            String::from("Operands must be strings, numbers or booleans."),
+                        )),
                     },
                 }
             }
             Expression::TernaryExpression(if_expr, ternary_op, left_expr, right_expr) => {
-                let line = if_expr.line();
                 let expr = self.evaluate(&*if_expr)?;
                 let left = self.evaluate(&*left_expr)?;
                 let right = self.evaluate(&*right_expr)?;
@@ -361,17 +874,17 @@ impl Interpreter {
                     TernaryOperator::QUESTIONMARK => match expr {
                         Literal::Boolean(true) => Ok(left),
                         Literal::Boolean(false) => Ok(right),
-                        _ => Err(Error {
-                            line: line.into(),
-                            message: String::from("Expression must evaluate to boolean"),
-                        }),
+                        _ => Err(RuntimeError::new(
+                            if_expr.span(),
+                            String::from("Expression must evaluate to boolean"),
+                        )),
                     },
                 }
             }
-            Expression::Variable(id) => self.lookup(id, id.get_depth()),
+            Expression::Variable(id) | Expression::This(id) => self.lookup(id),
             Expression::Assignment(id, assignment_expr) => {
                 let value = self.evaluate(&*assignment_expr)?;
-                self.assign(id, value, id.get_depth())
+                self.assign(id, value)
             }
             Expression::Or(left_expr, operator, right_expr)
             | Expression::And(left_expr, operator, right_expr) => {
@@ -392,38 +905,111 @@ impl Interpreter {
             }
         }
     }
-    pub fn lookup(&self, id: &Variable, depth: Option<usize>) -> Result<Literal> {
-        let environment = match depth {
-            Some(depth) => Environment::ancestor(&self.environment, depth),
-            None => Environment::get_global(&self.environment),
-        };
+    /// A new scope nested in `enclosing`, tracked by the collector.
+    pub fn new_environment(
+        &mut self,
+        enclosing: &Rc<RefCell<Environment>>,
+    ) -> Rc<RefCell<Environment>> {
+        let environment = Rc::new(RefCell::new(Environment::new_with_environment(enclosing)));
+        self.track(&environment);
         environment
-            .and_then(|env| env.borrow_mut().get(id.get_identifier().get_name()))
-            .ok_or_else(|| Error {
-                line: id.get_identifier().get_line(),
-                message: String::from(format!(
-                    "Undefined {} variable.",
-                    id.get_identifier().get_name()
-                )),
-            })
-    }
-
-    pub fn assign(&self, id: &Variable, value: Literal, depth: Option<usize>) -> Result<Literal> {
-        let environment = match depth {
-            Some(depth) => Environment::ancestor(&self.environment, depth),
-            None => Environment::get_global(&self.environment),
+    }
+
+    /// Checks the call depth limit for the innermost call, which applies even
+    /// outside of sandboxes.
+    pub fn enter_call(&self) -> RuntimeResult<()> {
+        self.budget.enter_call(self.frames.len()).map_err(|limit| {
+            let mut err = self.budget.error(limit, self.call_span());
+            // The refused call never ran, so like the VM the trace starts at
+            // its caller.
+            err.trace = self.stack_trace(err.line).split_off(1);
+            err
+        })
+    }
+
+    /// Checks a string of `len` bytes, or a list or map of `len` elements,
+    /// against the allocation limit before building it at `span`.
+    fn check_length(&self, len: usize, span: Span) -> RuntimeResult<()> {
+        self.budget
+            .check_length(len)
+            .map_err(|limit| self.budget.error(limit, span))
+    }
+
+    /// The arguments of a native called at the innermost call site, which
+    /// may build strings and lists as long as the allocation limit allows.
+    pub fn native_args<'a>(&self, args: &'a [Literal]) -> Args<'a> {
+        Args::new(args, self.call_span()).with_max_length(self.budget.max_length())
+    }
+
+    /// Reports the failure of a native called at the innermost call site.
+    pub fn native_error(&self, err: NativeError) -> RuntimeError {
+        match err {
+            NativeError::Limit(limit) => self.budget.error(limit, self.call_span()),
+            err => RuntimeError::new(self.call_span(), err.to_string()),
+        }
+    }
+
+    /// Span of the innermost call being executed, which natives use to
+    /// report their errors at the call site.
+    pub fn call_span(&self) -> Span {
+        self.frames
+            .last()
+            .map_or(Span::default(), |frame| frame.span)
+    }
+
+    /// Builds the trace for an error raised at `line` in the innermost frame,
+    /// listing each active function with the line it is currently executing.
+    fn stack_trace(&self, line: usize) -> Vec<StackFrame> {
+        let mut trace = vec![];
+        let mut line = line;
+        for frame in self.frames.iter().rev() {
+            trace.push(StackFrame {
+                function: frame.function.clone(),
+                line,
+            });
+            line = frame.line;
+        }
+        trace.push(StackFrame {
+            function: String::from("<script>"),
+            line,
+        });
+        trace
+    }
+
+    pub fn lookup(&self, id: &Variable) -> RuntimeResult<Literal> {
+        let value = match (id.get_depth(), id.get_slot()) {
+            (Some(depth), Some(slot)) => Environment::ancestor(&self.environment, depth)
+                .and_then(|env| env.borrow().get_at(slot)),
+            _ => Environment::get_global(&self.environment)
+                .and_then(|env| env.borrow().get(&id.get_identifier().get_name())),
         };
-        environment
-            .and_then(|env| {
+        value.ok_or_else(|| self.undefined_variable(id))
+    }
+
+    pub fn assign(&self, id: &Variable, value: Literal) -> RuntimeResult<Literal> {
+        let previous = match (id.get_depth(), id.get_slot()) {
+            (Some(depth), Some(slot)) => Environment::ancestor(&self.environment, depth)
+                .and_then(|env| env.borrow_mut().assign_at(slot, value.clone())),
+            _ => Environment::get_global(&self.environment).and_then(|env| {
                 env.borrow_mut()
-                    .assign(id.get_identifier().get_name(), value)
-            })
-            .ok_or_else(|| Error {
-                line: id.get_identifier().get_line(),
-                message: String::from(format!(
-                    "Undefined {} variable.",
-                    id.get_identifier().get_name()
-                )),
-            })
+                    .assign(&id.get_identifier().get_name(), value.clone())
+            }),
+        };
+        previous
+            .map(|_| value)
+            .ok_or_else(|| self.undefined_variable(id))
+    }
+
+    fn undefined_variable(&self, id: &Variable) -> RuntimeError {
+        RuntimeError::new(
+            id.get_identifier().get_span(),
+            format!("Undefined {} variable.", id.get_identifier().get_name()),
+        )
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
     }
 }
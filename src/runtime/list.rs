@@ -0,0 +1,76 @@
+use super::{Literal, RuntimeError, RuntimeResult, Span};
+use crate::gc::{Trace, Tracer};
+use std::cell::RefCell;
+
+/// Checks that `index` addresses one of the `len` elements of a list.
+pub fn element_index(index: Option<f64>, len: usize, span: Span) -> RuntimeResult<usize> {
+    checked_index(index, len, len, "list", span)
+}
+
+/// Checks that `index` is a position between elements of a list, which also
+/// allows the position right after the last element. Used for slices and inserts.
+pub fn boundary_index(index: Option<f64>, len: usize, span: Span) -> RuntimeResult<usize> {
+    checked_index(index, len, len + 1, "list", span)
+}
+
+/// Checks that `index` addresses one of the `len` characters of a string.
+/// Strings are indexed by character, not by byte.
+pub fn char_index(index: Option<f64>, len: usize, span: Span) -> RuntimeResult<usize> {
+    checked_index(index, len, len, "string", span)
+}
+
+/// Checks that `index` is a position between characters of a string,
+/// including the position right after the last one.
+pub fn char_boundary_index(index: Option<f64>, len: usize, span: Span) -> RuntimeResult<usize> {
+    checked_index(index, len, len + 1, "string", span)
+}
+
+/// `index` is `None` when the indexing value is not a number at all. Valid
+/// positions are below `end`, which is one past the length for boundaries.
+fn checked_index(
+    index: Option<f64>,
+    len: usize,
+    end: usize,
+    kind: &str,
+    span: Span,
+) -> RuntimeResult<usize> {
+    let position = match index {
+        Some(n) if n.fract() == 0.0 => n as i64,
+        _ => {
+            return Err(RuntimeError::new(
+                span,
+                format!(
+                    "{}{} index must be an integer.",
+                    kind[..1].to_uppercase(),
+                    &kind[1..]
+                ),
+            ))
+        }
+    };
+    if position < 0 || position as usize >= end {
+        return Err(RuntimeError::new(
+            span,
+            format!(
+                "Index {} out of bounds for {} of length {}.",
+                position, kind, len
+            ),
+        ));
+    }
+    Ok(position as usize)
+}
+
+impl Trace for RefCell<Vec<Literal>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(list) = self.try_borrow() {
+            for element in list.iter() {
+                element.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut list) = self.try_borrow_mut() {
+            list.clear();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use super::{Literal, RuntimeError, RuntimeResult, Span};
+use crate::gc::{Trace, Tracer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// The subset of literals that can be used as map keys. Keys compare the same
+/// way `Literal`s do, so `0` and `-0` address the same entry.
+#[derive(Debug, Clone)]
+pub enum MapKey {
+    String(String),
+    Number(f64),
+    Boolean(bool),
+    Nil,
+}
+
+impl MapKey {
+    pub fn from_literal(literal: &Literal, span: Span) -> RuntimeResult<Self> {
+        match literal {
+            Literal::String(s) => Ok(MapKey::String(s.clone())),
+            // NaN is never equal to itself, so it could never be looked up again.
+            Literal::Number(n) if !n.is_nan() => Ok(MapKey::Number(*n)),
+            Literal::Boolean(b) => Ok(MapKey::Boolean(*b)),
+            Literal::Nil => Ok(MapKey::Nil),
+            _ => Err(RuntimeError::new(
+                span,
+                String::from("Map keys must be strings, numbers, booleans or nil."),
+            )),
+        }
+    }
+
+    pub fn to_literal(&self) -> Literal {
+        match self {
+            MapKey::String(s) => Literal::String(s.clone()),
+            MapKey::Number(n) => Literal::Number(*n),
+            MapKey::Boolean(b) => Literal::Boolean(*b),
+            MapKey::Nil => Literal::Nil,
+        }
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &MapKey) -> bool {
+        match (self, other) {
+            (MapKey::String(a), MapKey::String(b)) => a == b,
+            (MapKey::Number(a), MapKey::Number(b)) => a == b,
+            (MapKey::Boolean(a), MapKey::Boolean(b)) => a == b,
+            (MapKey::Nil, MapKey::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            MapKey::String(s) => s.hash(state),
+            // -0.0 and 0.0 are equal, so they must hash the same.
+            MapKey::Number(n) if *n == 0.0 => 0.0f64.to_bits().hash(state),
+            MapKey::Number(n) => n.to_bits().hash(state),
+            MapKey::Boolean(b) => b.hash(state),
+            MapKey::Nil => {}
+        }
+        std::mem::discriminant(self).hash(state);
+    }
+}
+
+/// A hash map that iterates in insertion order. Overwriting an existing key
+/// keeps its original position. The value type is generic so that the
+/// bytecode VM can store its own values in it.
+#[derive(Debug)]
+pub struct LoxMap<V = Literal> {
+    entries: Vec<(MapKey, V)>,
+    positions: HashMap<MapKey, usize>,
+}
+
+impl<V> Default for LoxMap<V> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<V: Clone> LoxMap<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &MapKey) -> Option<V> {
+        self.positions
+            .get(key)
+            .map(|position| self.entries[*position].1.clone())
+    }
+
+    pub fn contains_key(&self, key: &MapKey) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    pub fn insert(&mut self, key: MapKey, value: V) {
+        match self.positions.get(&key) {
+            Some(position) => self.entries[*position].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &MapKey) -> Option<V> {
+        let position = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+        for (key, _) in &self.entries[position..] {
+            if let Some(later) = self.positions.get_mut(key) {
+                *later -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(MapKey, V)> {
+        self.entries.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.positions.clear();
+    }
+}
+
+impl Trace for RefCell<LoxMap> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(map) = self.try_borrow() {
+            for (_, value) in map.iter() {
+                value.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut map) = self.try_borrow_mut() {
+            map.clear();
+        }
+    }
+}
@@ -5,15 +5,22 @@ pub mod environment;
 pub mod function;
 pub mod instance;
 pub mod interpreter;
+pub mod list;
+pub mod map;
+pub mod native;
 pub mod resolver;
 
 use super::ast::*;
-use super::common::{Error, Literal, Result};
-pub use builtins::Clock;
+use super::common::{Error, Literal, Result, RuntimeError, RuntimeResult, Span, StackFrame};
 pub use callable::LoxCallable;
-pub use class::LoxClass;
+pub use class::{LoxClass, Method};
 pub use environment::Environment;
 pub use function::LoxFunction;
 pub use instance::LoxInstance;
-pub use interpreter::Interpreter;
+pub use interpreter::{Interpreter, StatementResult};
+pub use map::{LoxMap, MapKey};
+pub use native::{
+    Args, Arity, FromLiteral, HostData, NativeClass, NativeError, NativeFunction, NativeMethod,
+    NativeModule, NativeResult, This,
+};
 pub use resolver::Resolver;
@@ -0,0 +1,520 @@
+use super::{
+    Interpreter, Literal, LoxCallable, LoxInstance, LoxMap, RuntimeError, RuntimeResult, Span,
+};
+use crate::gc::{Trace, Tracer};
+use crate::sandbox::Limit;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub type ListRef = Rc<RefCell<Vec<Literal>>>;
+pub type MapRef = Rc<RefCell<LoxMap>>;
+
+/// How many arguments a function accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    /// Variadic functions take at least this many arguments.
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match self {
+            Arity::Exact(arity) => count == *arity,
+            Arity::AtLeast(arity) => count >= *arity,
+        }
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(arity: usize) -> Self {
+        Arity::Exact(arity)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exact(arity) => write!(f, "{}", arity),
+            Arity::AtLeast(arity) => write!(f, "at least {}", arity),
+        }
+    }
+}
+
+/// Why a native function failed. The interpreter reports it as a runtime
+/// error at the call site.
+#[derive(Debug)]
+pub enum NativeError {
+    /// The argument at `index` could not be converted to the Rust type the
+    /// function asked for.
+    Argument {
+        index: usize,
+        expected: String,
+    },
+    Message(String),
+    /// The function would have gone past a limit of the sandbox, which
+    /// stops the program.
+    Limit(Limit),
+}
+
+impl fmt::Display for NativeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NativeError::Argument { index, expected } => {
+                write!(f, "Argument {} must be {}.", index + 1, expected)
+            }
+            NativeError::Message(message) => write!(f, "{}", message),
+            NativeError::Limit(limit) => write!(f, "{:?} limit exceeded.", limit),
+        }
+    }
+}
+
+impl From<String> for NativeError {
+    fn from(message: String) -> Self {
+        NativeError::Message(message)
+    }
+}
+
+impl From<&str> for NativeError {
+    fn from(message: &str) -> Self {
+        NativeError::Message(message.to_string())
+    }
+}
+
+/// Lets natives use the helpers the interpreter itself reports errors with.
+/// Those are raised at `Args::span`, so only the message needs to be kept.
+impl From<RuntimeError> for NativeError {
+    fn from(error: RuntimeError) -> Self {
+        NativeError::Message(error.message)
+    }
+}
+
+pub type NativeResult<T> = Result<T, NativeError>;
+
+/// A Rust type that Lox values can be converted to when passed to a native.
+pub trait FromLiteral: Sized {
+    /// What the value must be, as shown in errors: "a number".
+    fn expected() -> String;
+    fn from_literal(literal: &Literal) -> Option<Self>;
+}
+
+impl FromLiteral for Literal {
+    fn expected() -> String {
+        String::from("a value")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        Some(literal.clone())
+    }
+}
+
+impl FromLiteral for f64 {
+    fn expected() -> String {
+        String::from("a number")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        literal.as_number()
+    }
+}
+
+impl FromLiteral for i64 {
+    fn expected() -> String {
+        String::from("an integer")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        literal
+            .as_number()
+            .filter(|n| n.fract() == 0.0)
+            .map(|n| n as i64)
+    }
+}
+
+impl FromLiteral for bool {
+    fn expected() -> String {
+        String::from("a boolean")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        match literal {
+            Literal::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl FromLiteral for String {
+    fn expected() -> String {
+        String::from("a string")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        match literal {
+            Literal::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromLiteral for ListRef {
+    fn expected() -> String {
+        String::from("a list")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        match literal {
+            Literal::List(list) => Some(list.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromLiteral for MapRef {
+    fn expected() -> String {
+        String::from("a map")
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        match literal {
+            Literal::Map(map) => Some(map.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// `nil` converts to `None`, for optional arguments.
+impl<T: FromLiteral> FromLiteral for Option<T> {
+    fn expected() -> String {
+        format!("{} or nil", T::expected())
+    }
+
+    fn from_literal(literal: &Literal) -> Option<Self> {
+        match literal {
+            Literal::Nil => Some(None),
+            other => T::from_literal(other).map(Some),
+        }
+    }
+}
+
+/// The arguments of a native call. The arity has been checked before the
+/// function runs, so reading an argument within it can't go out of bounds.
+pub struct Args<'a> {
+    values: &'a [Literal],
+    span: Span,
+    max_length: Option<usize>,
+}
+
+impl<'a> Args<'a> {
+    pub fn new(values: &'a [Literal], span: Span) -> Self {
+        Self {
+            values,
+            span,
+            max_length: None,
+        }
+    }
+
+    /// Limits the strings, lists and maps the function may build, as the
+    /// allocation limit of a sandbox does.
+    pub fn with_max_length(mut self, max_length: Option<usize>) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The span of the call expression.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn value(&self, index: usize) -> &Literal {
+        &self.values[index]
+    }
+
+    pub fn get<T: FromLiteral>(&self, index: usize) -> NativeResult<T> {
+        T::from_literal(&self.values[index]).ok_or_else(|| NativeError::Argument {
+            index,
+            expected: T::expected(),
+        })
+    }
+
+    /// Checks a string of `len` bytes, or a list or map of `len` elements,
+    /// before the function builds it.
+    pub fn check_length(&self, len: usize) -> NativeResult<()> {
+        match self.max_length {
+            Some(max) if len > max => Err(NativeError::Limit(Limit::Allocations)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Converts the arguments from `index` on, which variadic functions use.
+    pub fn rest<T: FromLiteral>(&self, index: usize) -> NativeResult<Vec<T>> {
+        (index..self.values.len())
+            .map(|index| self.get(index))
+            .collect()
+    }
+}
+
+type NativeFn = dyn Fn(&Args) -> NativeResult<Literal>;
+
+/// A function implemented by a Rust closure.
+pub struct NativeFunction {
+    name: String,
+    arity: Arity,
+    function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: impl Into<Arity>,
+        function: impl Fn(&Args) -> NativeResult<Literal> + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            arity: arity.into(),
+            function: Box::new(function),
+        }
+    }
+
+    /// Runs the function on arguments that already passed the arity check.
+    pub fn invoke(&self, args: &Args) -> NativeResult<Literal> {
+        (self.function)(args)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// The closure is opaque to the collector, so whatever it captured stays alive.
+impl Trace for NativeFunction {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl LoxCallable for NativeFunction {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Literal>,
+    ) -> RuntimeResult<Literal> {
+        let result = self
+            .invoke(&interpreter.native_args(&args))
+            .map_err(|err| interpreter.native_error(err))?;
+        interpreter.track_value(&result);
+        Ok(result)
+    }
+
+    fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// A named group of native functions and constants that are defined as
+/// globals together.
+pub struct NativeModule {
+    name: String,
+    functions: Vec<NativeFunction>,
+    constants: Vec<(String, Literal)>,
+}
+
+impl NativeModule {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            functions: vec![],
+            constants: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn function(
+        mut self,
+        name: &str,
+        arity: impl Into<Arity>,
+        function: impl Fn(&Args) -> NativeResult<Literal> + 'static,
+    ) -> Self {
+        self.functions
+            .push(NativeFunction::new(name, arity, function));
+        self
+    }
+
+    pub fn constant(mut self, name: &str, value: Literal) -> Self {
+        self.constants.push((name.to_string(), value));
+        self
+    }
+
+    /// Splits the module into the globals it defines.
+    pub fn into_parts(self) -> (Vec<NativeFunction>, Vec<(String, Literal)>) {
+        (self.functions, self.constants)
+    }
+}
+
+/// The Rust value an instance of a native class carries, set by its `init`.
+pub type HostData = RefCell<Option<Rc<dyn Any>>>;
+
+/// The instance a native method was called on.
+pub struct This<'a> {
+    class: &'a str,
+    data: &'a HostData,
+}
+
+impl<'a> This<'a> {
+    pub fn new(class: &'a str, data: &'a HostData) -> Self {
+        Self { class, data }
+    }
+
+    /// Attaches `data` to the instance, replacing what it held before.
+    pub fn set_data<T: Any>(&self, data: T) {
+        *self.data.borrow_mut() = Some(Rc::new(data));
+    }
+
+    /// The host data of the instance. Fails when `init` didn't set any, for
+    /// example because a subclass overrode it without calling `super.init`.
+    pub fn data<T: Any>(&self) -> NativeResult<Rc<T>> {
+        self.data
+            .borrow()
+            .clone()
+            .and_then(|data| data.downcast::<T>().ok())
+            .ok_or_else(|| {
+                NativeError::Message(format!("This {} instance was not initialized.", self.class))
+            })
+    }
+}
+
+type MethodFn = dyn Fn(&This, &Args) -> NativeResult<Literal>;
+
+/// A method of a native class implemented by a Rust closure.
+pub struct NativeMethod {
+    name: String,
+    class: String,
+    arity: Arity,
+    function: Box<MethodFn>,
+}
+
+impl NativeMethod {
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// Runs the method on `data`, the host data of the instance it is bound to.
+    pub fn invoke(&self, data: &HostData, args: &Args) -> NativeResult<Literal> {
+        (self.function)(&This::new(&self.class, data), args)
+    }
+}
+
+impl fmt::Debug for NativeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native method {}.{}>", self.class, self.name)
+    }
+}
+
+/// A native method bound to the instance it was looked up on.
+#[derive(Debug)]
+pub struct BoundNativeMethod {
+    instance: Rc<LoxInstance>,
+    method: Rc<NativeMethod>,
+}
+
+impl BoundNativeMethod {
+    pub fn new(instance: Rc<LoxInstance>, method: Rc<NativeMethod>) -> Self {
+        Self { instance, method }
+    }
+}
+
+impl Trace for BoundNativeMethod {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.instance);
+    }
+}
+
+impl LoxCallable for BoundNativeMethod {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        args: Vec<Literal>,
+    ) -> RuntimeResult<Literal> {
+        let result = self
+            .method
+            .invoke(self.instance.host_data(), &interpreter.native_args(&args))
+            .map_err(|err| interpreter.native_error(err))?;
+        interpreter.track_value(&result);
+        // Like initializers written in Lox, `init` returns the instance.
+        if self.method.name == "init" {
+            return Ok(Literal::Instance(self.instance.clone()));
+        }
+        Ok(result)
+    }
+
+    fn arity(&self) -> Arity {
+        self.method.arity
+    }
+
+    fn name(&self) -> String {
+        self.method.name()
+    }
+}
+
+/// A class whose methods are implemented in Rust. Its instances carry host
+/// data, which `init` attaches with `This::set_data` and the other methods
+/// read back with `This::data`. Script classes can inherit from it.
+pub struct NativeClass {
+    name: String,
+    methods: HashMap<String, Rc<NativeMethod>>,
+}
+
+impl NativeClass {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            methods: HashMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn method(
+        mut self,
+        name: &str,
+        arity: impl Into<Arity>,
+        function: impl Fn(&This, &Args) -> NativeResult<Literal> + 'static,
+    ) -> Self {
+        let method = NativeMethod {
+            name: name.to_string(),
+            class: self.name.clone(),
+            arity: arity.into(),
+            function: Box::new(function),
+        };
+        self.methods.insert(name.to_string(), Rc::new(method));
+        self
+    }
+
+    pub fn into_parts(self) -> (String, HashMap<String, Rc<NativeMethod>>) {
+        (self.name, self.methods)
+    }
+}
@@ -8,22 +8,27 @@ pub struct ResolverVariable {
     initialized: bool,
     usages: u32,
     pub line: usize,
+    /// Position of the variable in its scope, which is also its index in the
+    /// `slots` of the matching runtime `Environment`.
+    pub slot: usize,
 }
 
 impl ResolverVariable {
-    pub fn unresolved(line: usize) -> Self {
+    pub fn unresolved(line: usize, slot: usize) -> Self {
         Self {
             initialized: false,
             usages: 0,
             line,
+            slot,
         }
     }
 
-    pub fn resolved(line: usize) -> Self {
+    pub fn resolved(line: usize, slot: usize) -> Self {
         Self {
             initialized: true,
             usages: 0,
             line,
+            slot,
         }
     }
 
@@ -39,11 +44,26 @@ impl ResolverVariable {
 pub enum FunctionKind {
     None,
     Function,
+    Method,
+    Initializer,
+}
+
+pub enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+pub enum LoopKind {
+    None,
+    Loop,
 }
 
 pub struct Resolver {
     scopes: Vec<HashMap<String, ResolverVariable>>,
     current_function: FunctionKind,
+    current_class: ClassKind,
+    current_loop: LoopKind,
 }
 
 impl Resolver {
@@ -51,6 +71,29 @@ impl Resolver {
         Self {
             scopes: Vec::new(),
             current_function: FunctionKind::None,
+            current_class: ClassKind::None,
+            current_loop: LoopKind::None,
+        }
+    }
+
+    /// Resolves a whole program. Resolution of a top level statement stops at
+    /// its first error, but the remaining statements are still checked so that
+    /// all of the errors can be reported at once.
+    pub fn resolve(&mut self, statements: &mut [Statement]) -> std::result::Result<(), Vec<Error>> {
+        let mut errors = vec![];
+        for statement in statements.iter_mut() {
+            if let Err(err) = self.resolve_statement(statement) {
+                errors.push(err);
+                self.scopes.clear();
+                self.current_function = FunctionKind::None;
+                self.current_class = ClassKind::None;
+                self.current_loop = LoopKind::None;
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 
@@ -73,9 +116,52 @@ impl Resolver {
                 self.define(&func.identifier);
                 self.resolve_function(&func.parameters, &mut func.body, FunctionKind::Function)?;
             }
-            Statement::ClassDeclaration(name, _methods) => {
+            Statement::ClassDeclaration(name, superclass, methods) => {
+                let enclosing_class = mem::replace(&mut self.current_class, ClassKind::Class);
                 self.declare(name)?;
                 self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if superclass.get_identifier().get_name() == name.get_name() {
+                        return Err(Error::new(
+                            superclass.get_identifier().get_span(),
+                            String::from("A class can't inherit from itself."),
+                        ));
+                    }
+                    self.current_class = ClassKind::Subclass;
+                    self.resolve_local(superclass);
+
+                    self.begin_scope();
+                    if let Some(scope) = self.peek_scope() {
+                        scope.insert(
+                            String::from("super"),
+                            ResolverVariable::resolved(name.get_line(), 0),
+                        );
+                    }
+                }
+
+                self.begin_scope();
+                if let Some(scope) = self.peek_scope() {
+                    scope.insert(
+                        String::from("this"),
+                        ResolverVariable::resolved(name.get_line(), 0),
+                    );
+                }
+                for method in methods {
+                    if let Statement::FunctionDeclaration(func) = &mut **method {
+                        let kind = if func.get_identifier() == "init" {
+                            FunctionKind::Initializer
+                        } else {
+                            FunctionKind::Method
+                        };
+                        self.resolve_function(&func.parameters, &mut func.body, kind)?;
+                    }
+                }
+                self.end_scope()?;
+                if superclass.is_some() {
+                    self.end_scope()?;
+                }
+                self.current_class = enclosing_class;
             }
             Statement::ExpressionStatement(expr) => {
                 self.resolve_expr(expr)?;
@@ -90,20 +176,78 @@ impl Resolver {
             Statement::PrintStatement(print_expr) => {
                 self.resolve_expr(print_expr)?;
             }
-            Statement::ReturnStatement(return_expr) => {
+            Statement::ReturnStatement(keyword, return_expr) => {
                 if let FunctionKind::None = self.current_function {
-                    return Err(Error {
-                        line: return_expr.as_ref().unwrap().line(),
-                        message: String::from("Can't return form top-level code."),
-                    });
+                    return Err(Error::new(
+                        keyword.span,
+                        String::from("Can't return form top-level code."),
+                    ));
                 };
                 if let Some(expr) = &mut *return_expr {
+                    if let FunctionKind::Initializer = self.current_function {
+                        return Err(Error::new(
+                            keyword.span,
+                            String::from("Can't return a value from an initializer."),
+                        ));
+                    }
                     self.resolve_expr(expr)?;
                 }
             }
-            Statement::WhileStatement(condition, body) => {
+            Statement::WhileStatement(condition, body, increment) => {
                 self.resolve_expr(condition)?;
+                if let Some(increment) = &mut *increment {
+                    self.resolve_expr(increment)?;
+                }
+                let enclosing_loop = mem::replace(&mut self.current_loop, LoopKind::Loop);
                 self.resolve_statement(body)?;
+                self.current_loop = enclosing_loop;
+            }
+            Statement::BreakStatement(keyword) => {
+                if let LoopKind::None = self.current_loop {
+                    return Err(Error::new(
+                        keyword.span,
+                        String::from("Can't use 'break' outside of a loop."),
+                    ));
+                }
+            }
+            Statement::ContinueStatement(keyword) => {
+                if let LoopKind::None = self.current_loop {
+                    return Err(Error::new(
+                        keyword.span,
+                        String::from("Can't use 'continue' outside of a loop."),
+                    ));
+                }
+            }
+            Statement::ThrowStatement(_keyword, value) => {
+                self.resolve_expr(value)?;
+            }
+            Statement::TryStatement(_keyword, body, catch, finally) => {
+                self.begin_scope();
+                self.resolve_statements(body)?;
+                self.end_scope()?;
+                if let Some((name, handler)) = catch {
+                    // The caught value is the first variable of the handler's
+                    // scope, followed by the variables the handler declares.
+                    self.begin_scope();
+                    self.declare(name)?;
+                    self.define(name);
+                    self.resolve_statements(handler)?;
+                    self.end_scope()?;
+                }
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve_statements(finally)?;
+                    self.end_scope()?;
+                }
+            }
+            // Imports define globals, which are looked up dynamically.
+            Statement::ImportStatement(keyword, _, _) => {
+                if !self.scopes.is_empty() {
+                    return Err(Error::new(
+                        keyword.span,
+                        String::from("Can only import at the top level."),
+                    ));
+                }
             }
         }
         Ok(())
@@ -118,18 +262,45 @@ impl Resolver {
                     {
                         initializer.increment_usages();
                         if !initializer.initialized {
-                            return Err(Error {
-                                line: variable.get_identifier().get_line(),
-                                message: String::from(
-                                    "Can't read local variable in its own initializer",
-                                ),
-                            });
+                            return Err(Error::new(
+                                variable.get_identifier().get_span(),
+                                String::from("Can't read local variable in its own initializer"),
+                            ));
                         }
                     }
                 }
                 self.resolve_local(variable);
                 Ok(())
             }
+            Expression::This(variable) => {
+                if let ClassKind::None = self.current_class {
+                    return Err(Error::new(
+                        variable.get_identifier().get_span(),
+                        String::from("Can't use 'this' outside of a class."),
+                    ));
+                }
+                self.resolve_local(variable);
+                Ok(())
+            }
+            Expression::Super(keyword, _method) => {
+                match self.current_class {
+                    ClassKind::None => {
+                        return Err(Error::new(
+                            keyword.get_identifier().get_span(),
+                            String::from("Can't use 'super' outside of a class."),
+                        ))
+                    }
+                    ClassKind::Class => {
+                        return Err(Error::new(
+                            keyword.get_identifier().get_span(),
+                            String::from("Can't use 'super' in a class with no superclass."),
+                        ))
+                    }
+                    ClassKind::Subclass => {}
+                }
+                self.resolve_local(keyword);
+                Ok(())
+            }
             Expression::GetExpression(_name, expr) => {
                 self.resolve_expr(expr)?;
                 Ok(())
@@ -139,6 +310,40 @@ impl Resolver {
                 self.resolve_expr(object)?;
                 Ok(())
             }
+            Expression::IndexGetExpression(object, _bracket, index) => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                Ok(())
+            }
+            Expression::IndexSetExpression(object, _bracket, index, value) => {
+                self.resolve_expr(object)?;
+                self.resolve_expr(index)?;
+                self.resolve_expr(value)?;
+                Ok(())
+            }
+            Expression::SliceExpression(object, _bracket, start, end) => {
+                self.resolve_expr(object)?;
+                if let Some(start) = &mut *start {
+                    self.resolve_expr(start)?;
+                }
+                if let Some(end) = &mut *end {
+                    self.resolve_expr(end)?;
+                }
+                Ok(())
+            }
+            Expression::ListExpression(elements) => {
+                for element in elements {
+                    self.resolve_expr(element)?;
+                }
+                Ok(())
+            }
+            Expression::MapExpression(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key)?;
+                    self.resolve_expr(value)?;
+                }
+                Ok(())
+            }
             Expression::Assignment(variable, assignment_expr) => {
                 self.resolve_expr(assignment_expr)?;
                 self.resolve_local(variable);
@@ -191,10 +396,13 @@ impl Resolver {
         Ok(())
     }
 
+    /// Variables found in no scope are left unresolved and looked up as globals.
     fn resolve_local(&mut self, variable: &mut Variable) {
-        for (i, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&variable.get_identifier().get_name()) {
-                variable.set_depth(i);
+        let name = variable.get_identifier().get_name();
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(resolved) = scope.get(&name) {
+                variable.resolve(depth, resolved.slot);
+                return;
             }
         }
     }
@@ -206,6 +414,7 @@ impl Resolver {
         function_kind: FunctionKind,
     ) -> Result<()> {
         let enclosing_function = mem::replace(&mut self.current_function, function_kind);
+        let enclosing_loop = mem::replace(&mut self.current_loop, LoopKind::None);
         self.begin_scope();
         for param in params {
             self.declare(param)?;
@@ -214,20 +423,22 @@ impl Resolver {
         self.resolve_statements(body)?;
         self.end_scope()?;
         self.current_function = enclosing_function;
+        self.current_loop = enclosing_loop;
         Ok(())
     }
 
     fn declare(&mut self, name: &Identifier) -> Result<()> {
         if let Some(scope) = self.peek_scope() {
             if scope.contains_key(&name.get_name()) {
-                return Err(Error {
-                    line: name.get_line(),
-                    message: String::from("Already a variable with this name is in this scope."),
-                });
+                return Err(Error::new(
+                    name.get_span(),
+                    String::from("Already a variable with this name is in this scope."),
+                ));
             } else {
+                let slot = scope.len();
                 scope.insert(
                     name.get_name(),
-                    ResolverVariable::unresolved(name.get_line()),
+                    ResolverVariable::unresolved(name.get_line(), slot),
                 );
             }
         };
@@ -236,7 +447,16 @@ impl Resolver {
 
     fn define(&mut self, name: &Identifier) {
         if let Some(scope) = self.peek_scope() {
-            scope.insert(name.get_name(), ResolverVariable::resolved(name.get_line()));
+            match scope.get_mut(&name.get_name()) {
+                Some(variable) => variable.initialized = true,
+                None => {
+                    let slot = scope.len();
+                    scope.insert(
+                        name.get_name(),
+                        ResolverVariable::resolved(name.get_line(), slot),
+                    );
+                }
+            }
         };
     }
 
@@ -264,3 +484,9 @@ impl Resolver {
         Ok(())
     }
 }
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
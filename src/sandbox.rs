@@ -0,0 +1,261 @@
+//! Restricting what untrusted programs can do, for both backends.
+//!
+//! A `Sandbox` lists the native modules a program may use and the limits it
+//! runs under. The backend keeps a `Budget` that every step of the program is
+//! charged to, and stops the program with a `Limit` error once one runs out.
+//! Limit errors can't be caught by `try` statements, and `finally` blocks
+//! don't run for them, so a program can't keep itself going past a limit.
+
+use crate::common::{RuntimeError, Span};
+use crate::gc::Heap;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// The native modules of the standard library, by name.
+pub const MODULES: [&str; 5] = ["core", "time", "math", "string", "io"];
+
+/// The most calls that may run at once, sandboxed or not. Deep enough for any
+/// reasonable recursion, shallow enough to stop runaway recursion with an
+/// error rather than by exhausting memory.
+pub const MAX_CALL_DEPTH: usize = 10_000;
+
+/// The call depth `Sandbox::new` allows.
+const DEFAULT_CALL_DEPTH: usize = 100;
+
+/// How long the strings, lists and maps of a program may grow, per object its
+/// allocation limit allows. The collector doesn't see what objects hold, so
+/// without this a string doubling itself would exhaust memory within the
+/// limit.
+const LENGTH_PER_ALLOCATION: usize = 1024;
+
+/// How many steps pass between the checks of the time and allocation limits,
+/// which are too costly to make on every step.
+const CHECK_INTERVAL: u64 = 256;
+
+/// What a program is allowed to use and how much it may run. `Lox::new`
+/// allows everything; `Sandbox::new` allows nothing until told otherwise.
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    /// The native modules that are defined, or all of them if `None`.
+    modules: Option<HashSet<String>>,
+    imports: bool,
+    limits: Limits,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Limits {
+    instructions: Option<u64>,
+    call_depth: Option<usize>,
+    time: Option<Duration>,
+    allocations: Option<usize>,
+}
+
+impl Sandbox {
+    /// A sandbox without native modules or imports, limited to calls 100
+    /// deep and without other limits.
+    pub fn new() -> Self {
+        Self {
+            modules: Some(HashSet::new()),
+            imports: false,
+            limits: Limits {
+                call_depth: Some(DEFAULT_CALL_DEPTH),
+                ..Limits::default()
+            },
+        }
+    }
+
+    /// What programs get when they aren't sandboxed: only `MAX_CALL_DEPTH`
+    /// limits them.
+    pub fn unrestricted() -> Self {
+        Self {
+            modules: None,
+            imports: true,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Defines the native module `name`, one of `MODULES`: `"core"` for the
+    /// list, map and conversion functions and `gc`, `"time"` for `clock`,
+    /// `"math"`, `"string"`, and `"io"` for standard input and files.
+    pub fn allow_module(mut self, name: &str) -> Self {
+        if let Some(modules) = &mut self.modules {
+            modules.insert(name.to_string());
+        }
+        self
+    }
+
+    /// Lets `import` statements run files, which also lets a program read
+    /// parts of any file through the errors of importing it.
+    pub fn allow_imports(mut self) -> Self {
+        self.imports = true;
+        self
+    }
+
+    /// Limits the steps a call to `Lox::eval` may take. The tree-walker counts
+    /// the expressions it evaluates, the virtual machine its instructions.
+    pub fn max_instructions(mut self, instructions: u64) -> Self {
+        self.limits.instructions = Some(instructions);
+        self
+    }
+
+    /// Limits how many calls may be running at once. Depths beyond
+    /// `MAX_CALL_DEPTH` are lowered to it.
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.limits.call_depth = Some(depth);
+        self
+    }
+
+    /// Limits how long a call to `Lox::eval` may run. A native function that
+    /// blocks, such as `read_line`, can't be interrupted.
+    pub fn max_time(mut self, time: Duration) -> Self {
+        self.limits.time = Some(time);
+        self
+    }
+
+    /// Limits how many objects the collector tracks at once: instances,
+    /// lists, maps, and the scopes or upvalues that closures keep alive.
+    /// Strings may then hold 1024 bytes, and lists and maps 1024 elements,
+    /// per object allowed. Without this limit nothing stops a program from
+    /// exhausting memory.
+    pub fn max_allocations(mut self, allocations: usize) -> Self {
+        self.limits.allocations = Some(allocations);
+        self
+    }
+
+    pub fn allows_module(&self, name: &str) -> bool {
+        self.modules
+            .as_ref()
+            .is_none_or(|modules| modules.contains(name))
+    }
+
+    pub fn allows_imports(&self) -> bool {
+        self.imports
+    }
+
+    /// The budget a backend charges the program it runs to.
+    pub fn budget(&self) -> Budget {
+        Budget {
+            limits: self.limits,
+            steps: 0,
+            deadline: None,
+        }
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The limit a program ran out of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Instructions,
+    CallDepth,
+    Time,
+    Allocations,
+}
+
+/// What is left of the limits of a sandbox while a program runs.
+#[derive(Debug)]
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Starts a new run with the full budget.
+    pub fn start(&mut self) {
+        self.steps = 0;
+        self.deadline = self.limits.time.map(|time| Instant::now() + time);
+    }
+
+    /// Charges one step of the program. `heap` is collected before the
+    /// allocation limit is considered exceeded.
+    pub fn step(&mut self, heap: &mut Heap) -> Result<(), Limit> {
+        self.steps += 1;
+        if let Some(max) = self.limits.instructions {
+            if self.steps > max {
+                return Err(Limit::Instructions);
+            }
+        }
+        if !self.steps.is_multiple_of(CHECK_INTERVAL) {
+            return Ok(());
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(Limit::Time);
+        }
+        if let Some(max) = self.limits.allocations {
+            if heap.tracked() > max {
+                heap.collect();
+                if heap.tracked() > max {
+                    return Err(Limit::Allocations);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a call that makes `depth` calls run at once.
+    pub fn enter_call(&self, depth: usize) -> Result<(), Limit> {
+        if depth > self.max_call_depth() {
+            return Err(Limit::CallDepth);
+        }
+        Ok(())
+    }
+
+    /// Checks a string of `len` bytes, or a list or map of `len` elements,
+    /// that the program is about to build.
+    pub fn check_length(&self, len: usize) -> Result<(), Limit> {
+        match self.max_length() {
+            Some(max) if len > max => Err(Limit::Allocations),
+            _ => Ok(()),
+        }
+    }
+
+    /// The longest string, list or map a program may build, if its
+    /// allocations are limited.
+    pub fn max_length(&self) -> Option<usize> {
+        self.limits
+            .allocations
+            .map(|allocations| allocations.saturating_mul(LENGTH_PER_ALLOCATION))
+    }
+
+    fn max_call_depth(&self) -> usize {
+        self.limits
+            .call_depth
+            .map_or(MAX_CALL_DEPTH, |depth| depth.min(MAX_CALL_DEPTH))
+    }
+
+    /// The error stopping a program that ran out of `limit` at `span`.
+    pub fn error(&self, limit: Limit, span: Span) -> RuntimeError {
+        let mut err = RuntimeError::new(span, self.message(limit));
+        err.limit = Some(limit);
+        err
+    }
+
+    /// The message of the error stopping a program that ran out of `limit`.
+    pub fn message(&self, limit: Limit) -> String {
+        let limits = &self.limits;
+        match limit {
+            Limit::Instructions => format!(
+                "Instruction limit of {} exceeded.",
+                limits.instructions.unwrap_or_default()
+            ),
+            Limit::CallDepth => format!("Call depth limit of {} exceeded.", self.max_call_depth()),
+            Limit::Time => format!(
+                "Time limit of {} ms exceeded.",
+                limits.time.unwrap_or_default().as_millis()
+            ),
+            Limit::Allocations => format!(
+                "Allocation limit of {} objects exceeded.",
+                limits.allocations.unwrap_or_default()
+            ),
+        }
+    }
+}
@@ -1,6 +1,6 @@
 pub mod scanner;
 pub mod tokens;
 
-pub use super::common::{Error, Literal};
+pub use super::common::{Error, Literal, Span};
 pub use scanner::Scanner;
 pub use tokens::{Token, TokenType};
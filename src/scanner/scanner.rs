@@ -1,39 +1,115 @@
-use super::{Error, Literal, Token, TokenType};
+use super::{Error, Literal, Span, Token, TokenType};
 
+/// Splits source text into tokens. `start` and `current` are byte offsets into
+/// `source`; the spans of the tokens are shifted by `offset`, which gives
+/// every text run its own range of offsets in `Sources`.
 #[derive(Debug)]
 pub struct Scanner {
     source: String,
+    offset: usize,
     start: usize,
     current: usize,
     line: usize,
+    line_start: usize,
+    start_line: usize,
+    start_column: usize,
+    /// For every interpolated expression being scanned, innermost last, the
+    /// number of braces opened in it and not closed yet.
+    interpolations: Vec<usize>,
+    /// Whether the source ended inside a string or a block comment.
+    unterminated: bool,
     pub tokens: Vec<Token>,
-    pub had_error: bool,
+    pub errors: Vec<Error>,
 }
 
 impl Scanner {
-    pub fn new(source: String) -> Self {
+    pub fn new(source: String, offset: usize) -> Self {
         Self {
             source,
+            offset,
             tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
-            had_error: false,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
+            interpolations: vec![],
+            unterminated: false,
+            errors: vec![],
         }
     }
 
     pub fn scan_tokens(&mut self) {
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.source[self.line_start..self.start].chars().count() + 1;
             self.scan_token();
         }
 
-        let eof_token = Token::new(TokenType::EOF, String::from("EOF"), None, self.line);
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.source[self.line_start..self.start].chars().count() + 1;
+        let eof_token = Token::new(TokenType::EOF, String::from("EOF"), None, self.span());
         self.tokens.push(eof_token);
     }
 
+    /// Whether the scanned source ends inside a string, a block comment or
+    /// brackets of any kind, so that more of it is still to come. The REPL
+    /// keeps reading lines while it is.
+    pub fn is_incomplete(&self) -> bool {
+        let mut depth = 0isize;
+        for token in &self.tokens {
+            match token.typ {
+                TokenType::LEFTPAREN
+                | TokenType::LEFTBRACE
+                | TokenType::LEFTBRACKET
+                | TokenType::INTERPOLATION => depth += 1,
+                TokenType::RIGHTPAREN | TokenType::RIGHTBRACE | TokenType::RIGHTBRACKET => {
+                    depth -= 1
+                }
+                _ => {}
+            }
+        }
+        self.unterminated || depth > 0
+    }
+
     fn is_at_end(&self) -> bool {
-        self.current >= self.source.chars().count()
+        self.current >= self.source.len()
+    }
+
+    /// The span of the text scanned since the start of the current token.
+    fn span(&self) -> Span {
+        Span::new(
+            self.offset + self.start,
+            self.offset + self.current,
+            self.start_line,
+            self.start_column,
+        )
+    }
+
+    fn error(&mut self, message: &str) {
+        self.errors
+            .push(Error::new(self.span(), message.to_string()));
+    }
+
+    /// Reports an error from byte `start` of the current line to the
+    /// character scanned last, such as an escape sequence in a string.
+    fn error_at(&mut self, start: usize, message: &str) {
+        let column = self.source[self.line_start..start].chars().count() + 1;
+        let span = Span::new(
+            self.offset + start,
+            self.offset + self.current,
+            self.line,
+            column,
+        );
+        self.errors.push(Error::new(span, message.to_string()));
+    }
+
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
     }
 
     fn scan_token(&mut self) {
@@ -41,8 +117,30 @@ impl Scanner {
         match c {
             '(' => self.add_token(TokenType::LEFTPAREN),
             ')' => self.add_token(TokenType::RIGHTPAREN),
-            '{' => self.add_token(TokenType::LEFTBRACE),
-            '}' => self.add_token(TokenType::RIGHTBRACE),
+            '{' => {
+                if let Some(braces) = self.interpolations.last_mut() {
+                    *braces += 1;
+                }
+                self.add_token(TokenType::LEFTBRACE)
+            }
+            '}' => match self.interpolations.last_mut() {
+                // The brace closing an interpolated expression resumes the
+                // string it is in, as a token of its own.
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.add_token(TokenType::RIGHTBRACE);
+                    self.start = self.current;
+                    self.start_column += 1;
+                    self.string();
+                }
+                Some(braces) => {
+                    *braces -= 1;
+                    self.add_token(TokenType::RIGHTBRACE)
+                }
+                None => self.add_token(TokenType::RIGHTBRACE),
+            },
+            '[' => self.add_token(TokenType::LEFTBRACKET),
+            ']' => self.add_token(TokenType::RIGHTBRACKET),
             ',' => self.add_token(TokenType::COMMA),
             '.' => self.add_token(TokenType::DOT),
             '-' => self.add_token(TokenType::MINUS),
@@ -90,37 +188,55 @@ impl Scanner {
                         self.advance();
                     }
                 } else if self.match_token('*') {
-                    while !self.is_at_end() {
-                        self.advance();
-                        // A block comment goes until */
-                        while self.peek() == '*' && self.peek_next() == '/' {
-                            self.advance(); // Consume the */ part of the block comment
-                        }
-                    }
+                    self.block_comment();
                 } else {
                     self.add_token(TokenType::SLASH)
                 }
             }
             ' ' | '\r' | '\t' => (),
-            '\n' => self.line = self.line + 1,
-            '"' => self.string(),
+            '\n' => self.newline(),
+            '"' => {
+                if self.peek() == '"' && self.peek_next() == '"' {
+                    self.advance();
+                    self.advance();
+                    self.raw_string();
+                } else {
+                    self.string();
+                }
+            }
             c => {
                 if self.is_digit(c) {
                     self.number();
                 } else if self.is_alpha(c) {
                     self.identifier();
                 } else {
-                    Error::error(self.line, String::from("Unexpected character."));
-                    self.had_error = true;
+                    self.error("Unexpected character.");
                 }
             }
         }
     }
 
+    /// A block comment goes until */ and may span lines.
+    fn block_comment(&mut self) {
+        while !(self.peek() == '*' && self.peek_next() == '/') {
+            if self.is_at_end() {
+                self.unterminated = true;
+                self.error("Unterminated comment.");
+                return;
+            }
+            if self.advance() == '\n' {
+                self.newline();
+            }
+        }
+        // Consume the */ part of the block comment
+        self.advance();
+        self.advance();
+    }
+
     fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current);
-        self.current = self.current + 1;
-        c.unwrap()
+        let c = self.peek();
+        self.current += c.len_utf8();
+        c
     }
 
     fn add_token(&mut self, token: TokenType) {
@@ -128,7 +244,7 @@ impl Scanner {
         let current = self.current;
         let text = &self.source.as_str()[start..current];
         self.tokens
-            .push(Token::new(token, text.into(), None, self.line));
+            .push(Token::new(token, text.into(), None, self.span()));
     }
 
     fn add_token_literal(&mut self, token: TokenType, literal: Literal) {
@@ -136,54 +252,139 @@ impl Scanner {
         let current = self.current;
         let text = &self.source.as_str()[start..current];
         self.tokens
-            .push(Token::new(token, text.into(), Some(literal), self.line));
+            .push(Token::new(token, text.into(), Some(literal), self.span()));
     }
 
     fn match_token(&mut self, expected: char) -> bool {
         if self.is_at_end() {
             return false;
         }
-        if self.source.chars().nth(self.current).unwrap() != expected {
+        if self.peek() != expected {
             return false;
         }
-        self.current = self.current + 1;
+        self.current += expected.len_utf8();
         true
     }
 
     fn peek(&self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        let current = self.current + 1;
-        if current >= self.source.chars().count() {
-            return '\0';
-        }
-        self.source.chars().nth(current).unwrap()
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
+    /// Scans a string literal, or the rest of one after an interpolated
+    /// expression. `"a ${b} c"` is scanned as the INTERPOLATION `"a ${`, the
+    /// tokens of `b`, a RIGHTBRACE and the STRING ` c"`.
     fn string(&mut self) {
+        let mut text = String::new();
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line = self.line + 1;
+            if self.peek() == '$' && self.peek_next() == '{' {
+                self.advance();
+                self.advance();
+                self.interpolations.push(0);
+                self.add_token_literal(TokenType::INTERPOLATION, Literal::String(text));
+                return;
+            }
+            match self.advance() {
+                '\\' => {
+                    if let Some(c) = self.escape() {
+                        text.push(c);
+                    }
+                }
+                '\n' => {
+                    self.newline();
+                    text.push('\n');
+                }
+                c => text.push(c),
             }
-            self.advance();
         }
         if self.is_at_end() {
-            Error::error(self.line, String::from("Unterminated string."));
-            self.had_error = true;
+            self.unterminated = true;
+            self.error("Unterminated string.");
             return;
         }
 
         // Skip the trailing '"' from the string
         self.advance();
-        let start = self.start + 1;
-        let end = self.current - 1;
-        let string_literal = Literal::String(self.source[start..end].to_string());
-        self.add_token_literal(TokenType::STRING, string_literal)
+        self.add_token_literal(TokenType::STRING, Literal::String(text))
+    }
+
+    /// Reads the escape sequence after a backslash and returns the character
+    /// it stands for, or reports it and returns nothing if it is invalid.
+    fn escape(&mut self) -> Option<char> {
+        let start = self.current - 1;
+        if self.is_at_end() {
+            return None;
+        }
+        let c = match self.advance() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '"' => '"',
+            '\\' => '\\',
+            '$' => '$',
+            'u' => return self.unicode_escape(start),
+            c => {
+                if c == '\n' {
+                    self.newline();
+                }
+                let message = format!("Unknown escape sequence '\\{}'.", c.escape_default());
+                self.error_at(start, &message);
+                return None;
+            }
+        };
+        Some(c)
+    }
+
+    /// Reads the `{1F600}` part of a `\u{1F600}` escape, which names a
+    /// character by its code point in hexadecimal.
+    fn unicode_escape(&mut self, start: usize) -> Option<char> {
+        let mut digits = String::new();
+        if self.match_token('{') {
+            while self.peek().is_ascii_hexdigit() && digits.len() < 6 {
+                digits.push(self.advance());
+            }
+            if self.match_token('}') {
+                let c = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32);
+                if c.is_some() {
+                    return c;
+                }
+            }
+        }
+        self.error_at(start, "Invalid unicode escape sequence.");
+        None
+    }
+
+    /// Scans a string between triple quotes. It is taken verbatim, without
+    /// escapes or interpolation, except for a line break right after the
+    /// opening quotes, which lets the text start on a line of its own.
+    fn raw_string(&mut self) {
+        if self.peek() == '\r' && self.peek_next() == '\n' {
+            self.advance();
+        }
+        if self.match_token('\n') {
+            self.newline();
+        }
+        let text_start = self.current;
+        while !self.source[self.current..].starts_with("\"\"\"") && !self.is_at_end() {
+            if self.advance() == '\n' {
+                self.newline();
+            }
+        }
+        if self.is_at_end() {
+            self.unterminated = true;
+            self.error("Unterminated string.");
+            return;
+        }
+
+        let text = self.source[text_start..self.current].to_string();
+        self.current += 3;
+        self.add_token_literal(TokenType::STRING, Literal::String(text))
     }
 
     fn number(&mut self) {
@@ -211,19 +412,26 @@ impl Scanner {
         let identifier_token = self.source[self.start..self.current].to_string();
         match &identifier_token[..] {
             "and" => self.add_token(TokenType::AND),
+            "break" => self.add_token(TokenType::BREAK),
+            "catch" => self.add_token(TokenType::CATCH),
             "class" => self.add_token(TokenType::CLASS),
+            "continue" => self.add_token(TokenType::CONTINUE),
             "else" => self.add_token(TokenType::ELSE),
             "false" => self.add_token(TokenType::FALSE),
+            "finally" => self.add_token(TokenType::FINALLY),
             "for" => self.add_token(TokenType::FOR),
             "fun" => self.add_token(TokenType::FUN),
             "if" => self.add_token(TokenType::IF),
+            "import" => self.add_token(TokenType::IMPORT),
             "nil" => self.add_token(TokenType::NIL),
             "or" => self.add_token(TokenType::OR),
             "print" => self.add_token(TokenType::PRINT),
             "return" => self.add_token(TokenType::RETURN),
             "super" => self.add_token(TokenType::SUPER),
             "this" => self.add_token(TokenType::THIS),
+            "throw" => self.add_token(TokenType::THROW),
             "true" => self.add_token(TokenType::TRUE),
+            "try" => self.add_token(TokenType::TRY),
             "var" => self.add_token(TokenType::VAR),
             "while" => self.add_token(TokenType::WHILE),
             _ => self.add_token(TokenType::IDENTIFIER),
@@ -1,4 +1,4 @@
-use super::Literal;
+use super::{Literal, Span};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -8,6 +8,8 @@ pub enum TokenType {
     RIGHTPAREN,
     LEFTBRACE,
     RIGHTBRACE,
+    LEFTBRACKET,
+    RIGHTBRACKET,
     COMMA,
     DOT,
     MINUS,
@@ -31,6 +33,8 @@ pub enum TokenType {
     // Literals.
     NUMBER,
     STRING,
+    /// The part of a string literal up to an interpolated expression.
+    INTERPOLATION,
     IDENTIFIER,
 
     // Keywords.
@@ -38,14 +42,21 @@ pub enum TokenType {
     FALSE,
     TRUE,
     AND,
+    BREAK,
     CLASS,
+    CONTINUE,
     ELSE,
     FUN,
     FOR,
     IF,
+    IMPORT,
     OR,
     PRINT,
     RETURN,
+    THROW,
+    TRY,
+    CATCH,
+    FINALLY,
     SUPER,
     THIS,
     VAR,
@@ -61,6 +72,8 @@ impl Display for TokenType {
             TokenType::RIGHTPAREN => write!(f, ")"),
             TokenType::LEFTBRACE => write!(f, "{{"),
             TokenType::RIGHTBRACE => write!(f, "}}"),
+            TokenType::LEFTBRACKET => write!(f, "["),
+            TokenType::RIGHTBRACKET => write!(f, "]"),
             TokenType::COMMA => write!(f, ","),
             TokenType::DOT => write!(f, "."),
             TokenType::MINUS => write!(f, "-"),
@@ -78,14 +91,21 @@ impl Display for TokenType {
             TokenType::LESS => write!(f, "<"),
             TokenType::LESSEQUAL => write!(f, "<="),
             TokenType::AND => write!(f, "AND"),
+            TokenType::BREAK => write!(f, "BREAK"),
             TokenType::CLASS => write!(f, "CLASS"),
+            TokenType::CONTINUE => write!(f, "CONTINUE"),
             TokenType::ELSE => write!(f, "ELSE"),
             TokenType::FUN => write!(f, "FUN"),
             TokenType::FOR => write!(f, "FOR"),
             TokenType::IF => write!(f, "IF"),
+            TokenType::IMPORT => write!(f, "IMPORT"),
             TokenType::OR => write!(f, "OR"),
             TokenType::PRINT => write!(f, "PRINT"),
             TokenType::RETURN => write!(f, "RETURN"),
+            TokenType::THROW => write!(f, "THROW"),
+            TokenType::TRY => write!(f, "TRY"),
+            TokenType::CATCH => write!(f, "CATCH"),
+            TokenType::FINALLY => write!(f, "FINALLY"),
             TokenType::SUPER => write!(f, "SUPER"),
             TokenType::THIS => write!(f, "THIS"),
             TokenType::VAR => write!(f, "VAR"),
@@ -94,6 +114,7 @@ impl Display for TokenType {
             TokenType::FALSE => write!(f, "FALSE"),
             TokenType::IDENTIFIER => write!(f, "IDENTIFIER"),
             TokenType::STRING => write!(f, "STRING"),
+            TokenType::INTERPOLATION => write!(f, "INTERPOLATION"),
             TokenType::NUMBER => write!(f, "NUMBER"),
             TokenType::QUESTIONMARK => write!(f, "?"),
             TokenType::NIL => write!(f, "NIL"),
@@ -108,15 +129,17 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<Literal>,
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(typ: TokenType, lexeme: String, literal: Option<Literal>, line: usize) -> Self {
+    pub fn new(typ: TokenType, lexeme: String, literal: Option<Literal>, span: Span) -> Self {
         Self {
             typ,
             lexeme,
             literal,
-            line,
+            line: span.line,
+            span,
         }
     }
 }
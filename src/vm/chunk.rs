@@ -0,0 +1,179 @@
+use super::Value;
+use crate::common::Span;
+
+/// Instructions understood by the virtual machine. Operands follow the opcode
+/// byte in the code stream: constant, global and property names use a two byte
+/// constant index, locals, upvalues and argument counts use a single byte.
+/// Jumps, including to the handlers `Try` and `TryFinally` install, take a two
+/// byte offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    Dup,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Comma,
+    Not,
+    Negate,
+    Ternary,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+    BuildList,
+    BuildMap,
+    IndexGet,
+    IndexSet,
+    Slice,
+    Import,
+    Try,
+    TryFinally,
+    EndTry,
+    Throw,
+    Rethrow,
+    DiscardError,
+}
+
+impl OpCode {
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        use OpCode::*;
+        const OPCODES: [OpCode; 53] = [
+            Constant,
+            Nil,
+            True,
+            False,
+            Pop,
+            Dup,
+            GetLocal,
+            SetLocal,
+            GetGlobal,
+            DefineGlobal,
+            SetGlobal,
+            GetUpvalue,
+            SetUpvalue,
+            GetProperty,
+            SetProperty,
+            GetSuper,
+            Equal,
+            NotEqual,
+            Greater,
+            GreaterEqual,
+            Less,
+            LessEqual,
+            Add,
+            Subtract,
+            Multiply,
+            Divide,
+            Comma,
+            Not,
+            Negate,
+            Ternary,
+            Print,
+            Jump,
+            JumpIfFalse,
+            Loop,
+            Call,
+            Closure,
+            CloseUpvalue,
+            Return,
+            Class,
+            Inherit,
+            Method,
+            BuildList,
+            BuildMap,
+            IndexGet,
+            IndexSet,
+            Slice,
+            Import,
+            Try,
+            TryFinally,
+            EndTry,
+            Throw,
+            Rethrow,
+            DiscardError,
+        ];
+        OPCODES.get(byte as usize).copied()
+    }
+}
+
+/// Flags of the `Slice` operand telling which bounds were pushed on the stack.
+pub const SLICE_HAS_START: u8 = 0b01;
+pub const SLICE_HAS_END: u8 = 0b10;
+
+/// Flag of the `Import` operand telling that a single name is imported, whose
+/// constant follows.
+pub const IMPORT_HAS_NAME: u8 = 0b01;
+
+/// A compiled function body: the byte code, the constants it refers to and a
+/// run-length encoded table mapping byte offsets back to source spans.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    spans: Vec<(Span, usize)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        match self.spans.last_mut() {
+            Some((last_span, count)) if *last_span == span => *count += 1,
+            _ => self.spans.push((span, 1)),
+        }
+    }
+
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// The source span of the expression or statement that emitted the byte at `offset`.
+    pub fn span(&self, offset: usize) -> Span {
+        let mut end = 0;
+        for (span, count) in &self.spans {
+            end += count;
+            if offset < end {
+                return *span;
+            }
+        }
+        self.spans.last().map_or(Span::default(), |(span, _)| *span)
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+}
@@ -0,0 +1,897 @@
+use super::chunk::{IMPORT_HAS_NAME, SLICE_HAS_END, SLICE_HAS_START};
+use super::{Chunk, FunctionProto, OpCode, Value};
+use crate::ast::{
+    BinaryOperator, Expression, ExpressionNode, FunctionInfo, Identifier, Statement, Token,
+    UnaryOperator,
+};
+use crate::common::{Error, Literal, Result, Span};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+
+#[derive(PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+/// Jumps out of the innermost loop that still have to be pointed at their
+/// targets once the end of the loop is known.
+struct LoopState {
+    scope_depth: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// A `try` statement whose handler is installed while the code being compiled
+/// runs. Jumping out of it with `break`, `continue` or `return` removes the
+/// handler and runs the `finally` block first.
+#[derive(Clone)]
+struct TryState {
+    finally: Option<Vec<Statement>>,
+    /// The number of loops entered when the `try` statement was.
+    loops: usize,
+    /// Set while the `finally` block runs for an error it rethrows afterwards,
+    /// which jumping out of the block discards instead.
+    rethrowing: bool,
+}
+
+/// Everything the compiler tracks for the function whose body it is
+/// currently emitting code for.
+struct FunctionState {
+    name: String,
+    arity: usize,
+    kind: FunctionKind,
+    chunk: Chunk,
+    names: HashMap<String, u16>,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueRef>,
+    loops: Vec<LoopState>,
+    tries: Vec<TryState>,
+    scope_depth: usize,
+    span: Span,
+    source: Option<Rc<str>>,
+}
+
+impl FunctionState {
+    fn new(name: String, kind: FunctionKind) -> Self {
+        // Slot zero holds the callee, or the receiver inside methods.
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            name,
+            arity: 0,
+            kind,
+            chunk: Chunk::new(),
+            names: HashMap::new(),
+            locals: vec![Local {
+                name: receiver.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: vec![],
+            loops: vec![],
+            tries: vec![],
+            scope_depth: 0,
+            span: Span::default(),
+            source: None,
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u8> {
+        self.locals
+            .iter()
+            .rposition(|local| local.name == name)
+            .map(|slot| slot as u8)
+    }
+}
+
+/// Where a variable lives once the enclosing functions have been searched.
+enum VariableSlot {
+    Local(u8),
+    Upvalue(u8),
+    Global(u16),
+}
+
+/// Compiles a resolved program into the bytecode of its top level script.
+/// The resolver has already rejected programs with misplaced `return`,
+/// `this`, `super`, `break` or `continue`, so the compiler only reports
+/// limits of the bytecode format.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+}
+
+impl Compiler {
+    pub fn compile(statements: &[Statement], run_in_repl: bool) -> Result<Rc<FunctionProto>> {
+        let mut compiler = Compiler {
+            functions: vec![FunctionState::new(
+                String::from("<script>"),
+                FunctionKind::Script,
+            )],
+        };
+        // The value of a final expression statement is returned from the script
+        // instead of discarded, so that the script produces it.
+        let (last, rest) = match statements.split_last() {
+            Some((Statement::ExpressionStatement(expr), rest)) => (Some(expr), rest),
+            _ => (None, statements),
+        };
+        for statement in rest {
+            compiler.statement(statement, run_in_repl)?;
+        }
+        match last {
+            Some(expr) => {
+                compiler.expression(expr)?;
+                if run_in_repl {
+                    compiler.emit_op(OpCode::Dup, expr.span());
+                    compiler.emit_op(OpCode::Print, expr.span());
+                }
+                compiler.emit_op(OpCode::Return, expr.span());
+            }
+            None => compiler.emit_return(),
+        }
+        Ok(Rc::new(compiler.end_function().0))
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    /// `print_expressions` mirrors the tree-walker, which echoes the value of
+    /// expression statements that are not nested in a block when running in the REPL.
+    fn statement(&mut self, statement: &Statement, print_expressions: bool) -> Result<()> {
+        match statement {
+            Statement::PrintStatement(expr) => {
+                self.expression(expr)?;
+                self.emit_op(OpCode::Print, expr.span());
+            }
+            Statement::ExpressionStatement(expr) => {
+                self.expression(expr)?;
+                if print_expressions {
+                    self.emit_op(OpCode::Print, expr.span());
+                } else {
+                    self.emit_op(OpCode::Pop, expr.span());
+                }
+            }
+            Statement::VariableDeclaration(id, init_expr) => {
+                match init_expr {
+                    Some(expr) => self.expression(expr)?,
+                    None => self.emit_op(OpCode::Nil, id.get_span()),
+                }
+                self.define_variable(id)?;
+            }
+            Statement::FunctionDeclaration(func) => {
+                // Declared before the body is compiled so the function can call itself.
+                let global = self.declare_variable(&func.identifier)?;
+                self.function(func, FunctionKind::Function, func.identifier.get_span())?;
+                if let Some(name) = global {
+                    self.emit_op(OpCode::DefineGlobal, func.identifier.get_span());
+                    self.emit_u16(name, func.identifier.get_span());
+                }
+            }
+            Statement::ClassDeclaration(id, superclass, methods) => {
+                let span = id.get_span();
+                let name = self.name_constant(&id.get_name(), span)?;
+                let global = self.declare_variable(id)?;
+                self.emit_op(OpCode::Class, span);
+                self.emit_u16(name, span);
+                if let Some(name) = global {
+                    self.emit_op(OpCode::DefineGlobal, span);
+                    self.emit_u16(name, span);
+                }
+
+                if let Some(superclass) = superclass {
+                    let superclass_span = superclass.get_identifier().get_span();
+                    self.get_variable(&superclass.get_identifier().get_name(), superclass_span)?;
+                    self.begin_scope();
+                    self.add_local("super".to_string(), superclass_span)?;
+                    self.get_variable(&id.get_name(), span)?;
+                    self.emit_op(OpCode::Inherit, superclass_span);
+                }
+
+                self.get_variable(&id.get_name(), span)?;
+                for method in methods {
+                    if let Statement::FunctionDeclaration(func) = &**method {
+                        let method_span = func.identifier.get_span();
+                        let kind = if func.get_identifier() == "init" {
+                            FunctionKind::Initializer
+                        } else {
+                            FunctionKind::Method
+                        };
+                        self.function(func, kind, method_span)?;
+                        let name = self.name_constant(&func.get_identifier(), method_span)?;
+                        self.emit_op(OpCode::Method, method_span);
+                        self.emit_u16(name, method_span);
+                    }
+                }
+                self.emit_op(OpCode::Pop, span);
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+            }
+            Statement::BlockStatement(statements) => self.block(statements)?,
+            Statement::ImportStatement(_keyword, path, name) => {
+                let import = match &path.literal {
+                    Some(Literal::String(import)) => import.clone(),
+                    _ => path.lexeme.clone(),
+                };
+                let constant = self.name_constant(&import, path.span)?;
+                self.emit_op(OpCode::Import, path.span);
+                self.emit_u16(constant, path.span);
+                match name {
+                    Some(name) => {
+                        let span = name.get_span();
+                        let constant = self.name_constant(&name.get_name(), span)?;
+                        self.emit_byte(IMPORT_HAS_NAME, span);
+                        self.emit_u16(constant, span);
+                    }
+                    None => self.emit_byte(0, path.span),
+                }
+            }
+            Statement::ThrowStatement(_keyword, value) => {
+                self.expression(value)?;
+                self.emit_op(OpCode::Throw, value.span());
+            }
+            Statement::TryStatement(keyword, body, catch, finally) => {
+                self.try_statement(keyword, body, catch.as_ref(), finally.as_ref())?;
+            }
+            Statement::IfStatement(condition, then_branch, else_branch) => {
+                let span = condition.span();
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit_op(OpCode::Pop, span);
+                self.statement(then_branch, print_expressions)?;
+                let else_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(then_jump)?;
+                self.emit_op(OpCode::Pop, span);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch, print_expressions)?;
+                }
+                self.patch_jump(else_jump)?;
+            }
+            Statement::WhileStatement(condition, body, increment) => {
+                let span = condition.span();
+                let loop_start = self.current().chunk.code.len();
+                self.expression(condition)?;
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit_op(OpCode::Pop, span);
+
+                let scope_depth = self.current().scope_depth;
+                self.current().loops.push(LoopState {
+                    scope_depth,
+                    breaks: vec![],
+                    continues: vec![],
+                });
+                self.statement(body, print_expressions)?;
+                let state = self.current().loops.pop().unwrap();
+
+                for jump in state.continues {
+                    self.patch_jump(jump)?;
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment)?;
+                    self.emit_op(OpCode::Pop, increment.span());
+                }
+                self.emit_loop(loop_start, span)?;
+                self.patch_jump(exit_jump)?;
+                self.emit_op(OpCode::Pop, span);
+                // A break jumps past the pop above: it leaves the loop after the
+                // condition value has already been discarded.
+                for jump in state.breaks {
+                    self.patch_jump(jump)?;
+                }
+            }
+            Statement::ReturnStatement(keyword, return_expr) => {
+                if self.current().kind == FunctionKind::Initializer {
+                    if let Some(expr) = return_expr {
+                        self.expression(expr)?;
+                        self.emit_op(OpCode::Pop, keyword.span);
+                    }
+                    self.emit_op(OpCode::GetLocal, keyword.span);
+                    self.emit_byte(0, keyword.span);
+                } else {
+                    match return_expr {
+                        Some(expr) => self.expression(expr)?,
+                        None => self.emit_op(OpCode::Nil, keyword.span),
+                    }
+                }
+                if !self.current().tries.is_empty() {
+                    // The value returned stays on the stack while `finally`
+                    // blocks run, in a slot their locals have to skip.
+                    self.add_local(String::new(), keyword.span)?;
+                    self.exit_tries(0, keyword.span)?;
+                    self.current().locals.pop();
+                }
+                self.emit_op(OpCode::Return, keyword.span);
+            }
+            Statement::BreakStatement(keyword) => {
+                let loops = self.current().loops.len();
+                self.exit_tries(loops, keyword.span)?;
+                self.discard_loop_locals(keyword.span);
+                let jump = self.emit_jump(OpCode::Jump, keyword.span);
+                if let Some(state) = self.current().loops.last_mut() {
+                    state.breaks.push(jump);
+                }
+            }
+            Statement::ContinueStatement(keyword) => {
+                let loops = self.current().loops.len();
+                self.exit_tries(loops, keyword.span)?;
+                self.discard_loop_locals(keyword.span);
+                let jump = self.emit_jump(OpCode::Jump, keyword.span);
+                if let Some(state) = self.current().loops.last_mut() {
+                    state.continues.push(jump);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, statements: &[Statement]) -> Result<()> {
+        self.begin_scope();
+        for statement in statements {
+            self.statement(statement, false)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    /// The `try` block runs with a handler installed. Without a `catch`
+    /// clause the handler runs the `finally` block and rethrows the error.
+    /// Otherwise it jumps to the `catch` clause with the value caught in the
+    /// slot of its variable, and when there is a `finally` block a second
+    /// handler makes sure it also runs if the `catch` clause fails.
+    fn try_statement(
+        &mut self,
+        keyword: &Token,
+        body: &[Statement],
+        catch: Option<&(Identifier, Vec<Statement>)>,
+        finally: Option<&Vec<Statement>>,
+    ) -> Result<()> {
+        let span = keyword.span;
+        let handler = match catch {
+            Some(_) => self.emit_jump(OpCode::Try, span),
+            None => self.emit_jump(OpCode::TryFinally, span),
+        };
+        self.guarded(body, finally, span)?;
+        let exit = self.emit_jump(OpCode::Jump, span);
+        self.patch_jump(handler)?;
+
+        match (catch, finally) {
+            (Some((name, statements)), Some(finally)) => {
+                self.begin_scope();
+                self.add_local(name.get_name(), name.get_span())?;
+                let handler = self.emit_jump(OpCode::TryFinally, span);
+                self.guarded(statements, Some(finally), span)?;
+                let done = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(handler)?;
+                self.rethrow_after(finally, span)?;
+                self.patch_jump(done)?;
+                self.end_scope();
+            }
+            (Some((name, statements)), None) => {
+                self.begin_scope();
+                self.add_local(name.get_name(), name.get_span())?;
+                for statement in statements {
+                    self.statement(statement, false)?;
+                }
+                self.end_scope();
+            }
+            (None, Some(finally)) => self.rethrow_after(finally, span)?,
+            (None, None) => {}
+        }
+        self.patch_jump(exit)
+    }
+
+    /// Compiles the statements a handler was installed for, then removes it
+    /// and runs the `finally` block.
+    fn guarded(
+        &mut self,
+        statements: &[Statement],
+        finally: Option<&Vec<Statement>>,
+        span: Span,
+    ) -> Result<()> {
+        let loops = self.current().loops.len();
+        self.current().tries.push(TryState {
+            finally: finally.cloned(),
+            loops,
+            rethrowing: false,
+        });
+        self.block(statements)?;
+        self.current().tries.pop();
+        self.emit_op(OpCode::EndTry, span);
+        if let Some(finally) = finally {
+            self.block(finally)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the `finally` block for an error the handler saved, and raises it
+    /// again.
+    fn rethrow_after(&mut self, finally: &[Statement], span: Span) -> Result<()> {
+        let loops = self.current().loops.len();
+        self.current().tries.push(TryState {
+            finally: None,
+            loops,
+            rethrowing: true,
+        });
+        self.block(finally)?;
+        self.current().tries.pop();
+        self.emit_op(OpCode::Rethrow, span);
+        Ok(())
+    }
+
+    /// Removes the handlers of the `try` statements entered inside the
+    /// innermost `loops` loops, innermost first, and runs their `finally`
+    /// blocks, before a jump out of them.
+    fn exit_tries(&mut self, loops: usize, span: Span) -> Result<()> {
+        let mut index = self.current().tries.len();
+        while index > 0 && self.current().tries[index - 1].loops >= loops {
+            index -= 1;
+            let state = self.current().tries[index].clone();
+            if state.rethrowing {
+                self.emit_op(OpCode::DiscardError, span);
+                continue;
+            }
+            self.emit_op(OpCode::EndTry, span);
+            if let Some(finally) = &state.finally {
+                // A jump out of the `finally` block itself only leaves the
+                // statements around the `try` statement.
+                let inner = self.current().tries.split_off(index);
+                self.block(finally)?;
+                self.current().tries.extend(inner);
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr_node: &ExpressionNode) -> Result<()> {
+        let span = expr_node.span();
+        match expr_node.expr() {
+            Expression::Literal(literal) => match Value::from_literal(literal) {
+                Some(Value::Nil) => self.emit_op(OpCode::Nil, span),
+                Some(Value::Boolean(true)) => self.emit_op(OpCode::True, span),
+                Some(Value::Boolean(false)) => self.emit_op(OpCode::False, span),
+                Some(value) => self.emit_constant(value, span)?,
+                None => {
+                    return Err(Error::new(
+                        span,
+                        format!("Can't compile literal {}.", literal),
+                    ))
+                }
+            },
+            Expression::Grouping(expr) => self.expression(expr)?,
+            Expression::Unary(operator, expr) => {
+                self.expression(expr)?;
+                let op = match operator {
+                    UnaryOperator::MINUS => OpCode::Negate,
+                    UnaryOperator::BANG => OpCode::Not,
+                };
+                self.emit_op(op, span);
+            }
+            Expression::BinaryExpression(left, operator, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                let op = match operator {
+                    BinaryOperator::PLUS => OpCode::Add,
+                    BinaryOperator::MINUS => OpCode::Subtract,
+                    BinaryOperator::STAR => OpCode::Multiply,
+                    BinaryOperator::SLASH => OpCode::Divide,
+                    BinaryOperator::GREATER => OpCode::Greater,
+                    BinaryOperator::GREATEREQUAL => OpCode::GreaterEqual,
+                    BinaryOperator::LESS => OpCode::Less,
+                    BinaryOperator::LESSEQUAL => OpCode::LessEqual,
+                    BinaryOperator::EQUALEQUAL => OpCode::Equal,
+                    BinaryOperator::BANGEQUAL => OpCode::NotEqual,
+                    BinaryOperator::COMMA => OpCode::Comma,
+                };
+                self.emit_op(op, span);
+            }
+            Expression::TernaryExpression(condition, _operator, left, right) => {
+                // Like the tree-walker, both branches are evaluated before one is picked.
+                self.expression(condition)?;
+                self.expression(left)?;
+                self.expression(right)?;
+                self.emit_op(OpCode::Ternary, condition.span());
+            }
+            Expression::And(left, _operator, right) => {
+                self.expression(left)?;
+                let end_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit_op(OpCode::Pop, span);
+                self.expression(right)?;
+                self.patch_jump(end_jump)?;
+            }
+            Expression::Or(left, _operator, right) => {
+                self.expression(left)?;
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                let end_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(else_jump)?;
+                self.emit_op(OpCode::Pop, span);
+                self.expression(right)?;
+                self.patch_jump(end_jump)?;
+            }
+            Expression::Variable(variable) | Expression::This(variable) => {
+                let id = variable.get_identifier();
+                self.get_variable(&id.get_name(), id.get_span())?;
+            }
+            Expression::Assignment(variable, value) => {
+                self.expression(value)?;
+                let id = variable.get_identifier();
+                let (op, operand) = match self.resolve_variable(&id.get_name(), id.get_span())? {
+                    VariableSlot::Local(slot) => (OpCode::SetLocal, slot as u16),
+                    VariableSlot::Upvalue(index) => (OpCode::SetUpvalue, index as u16),
+                    VariableSlot::Global(name) => (OpCode::SetGlobal, name),
+                };
+                self.emit_variable_op(op, operand, id.get_span());
+            }
+            Expression::CallExpression(callee, _paren, args) => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                self.emit_op(OpCode::Call, span);
+                self.emit_byte(args.len() as u8, span);
+            }
+            Expression::GetExpression(name, object) => {
+                self.expression(object)?;
+                let name = self.name_constant(&name.get_name(), span)?;
+                self.emit_op(OpCode::GetProperty, span);
+                self.emit_u16(name, span);
+            }
+            Expression::SetExpression(object, name, value) => {
+                self.expression(object)?;
+                self.expression(value)?;
+                let name = self.name_constant(&name.get_name(), span)?;
+                self.emit_op(OpCode::SetProperty, span);
+                self.emit_u16(name, span);
+            }
+            Expression::Super(keyword, method) => {
+                let keyword_span = keyword.get_identifier().get_span();
+                self.get_variable("this", keyword_span)?;
+                self.get_variable("super", keyword_span)?;
+                let name = self.name_constant(&method.get_name(), span)?;
+                self.emit_op(OpCode::GetSuper, span);
+                self.emit_u16(name, span);
+            }
+            Expression::FunctionExpression(func) => {
+                self.function(func, FunctionKind::Function, span)?;
+            }
+            Expression::ListExpression(elements) => {
+                for element in elements {
+                    self.expression(element)?;
+                }
+                let count = self.count_operand(elements.len(), "list elements", span)?;
+                self.emit_op(OpCode::BuildList, span);
+                self.emit_u16(count, span);
+            }
+            Expression::MapExpression(entries) => {
+                for (key, value) in entries {
+                    self.expression(key)?;
+                    self.expression(value)?;
+                }
+                let count = self.count_operand(entries.len(), "map entries", span)?;
+                self.emit_op(OpCode::BuildMap, span);
+                self.emit_u16(count, span);
+            }
+            Expression::IndexGetExpression(object, _bracket, index) => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.emit_op(OpCode::IndexGet, span);
+            }
+            Expression::IndexSetExpression(object, _bracket, index, value) => {
+                self.expression(object)?;
+                self.expression(index)?;
+                self.expression(value)?;
+                self.emit_op(OpCode::IndexSet, span);
+            }
+            Expression::SliceExpression(object, _bracket, start, end) => {
+                self.expression(object)?;
+                let mut flags = 0;
+                if let Some(start) = start {
+                    self.expression(start)?;
+                    flags |= SLICE_HAS_START;
+                }
+                if let Some(end) = end {
+                    self.expression(end)?;
+                    flags |= SLICE_HAS_END;
+                }
+                self.emit_op(OpCode::Slice, span);
+                self.emit_byte(flags, span);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the body of a function and emits the instruction that creates
+    /// its closure at runtime.
+    fn function<F: FunctionInfo>(
+        &mut self,
+        func: &F,
+        kind: FunctionKind,
+        span: Span,
+    ) -> Result<()> {
+        self.functions
+            .push(FunctionState::new(func.identifier(), kind));
+        self.current().arity = func.parameters().len();
+        self.current().source = Some(func.source().clone());
+        self.begin_scope();
+        for parameter in func.parameters() {
+            self.add_local(parameter.get_name(), parameter.get_span())?;
+        }
+        for statement in func.body() {
+            self.statement(statement, false)?;
+        }
+        self.emit_return();
+
+        let (function, upvalues) = self.end_function();
+        let constant = self.make_constant(Value::Function(Rc::new(function)), span)?;
+        self.emit_op(OpCode::Closure, span);
+        self.emit_u16(constant, span);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8, span);
+            self.emit_byte(upvalue.index, span);
+        }
+        Ok(())
+    }
+
+    fn end_function(&mut self) -> (FunctionProto, Vec<UpvalueRef>) {
+        let state = self.functions.pop().unwrap();
+        let function = FunctionProto {
+            name: state.name,
+            arity: state.arity,
+            upvalue_count: state.upvalues.len(),
+            chunk: state.chunk,
+            source: state.source,
+        };
+        (function, state.upvalues)
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.current();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+        let span = state.span;
+        while let Some(local) = self.current().locals.pop() {
+            if local.depth <= depth {
+                self.current().locals.push(local);
+                break;
+            }
+            let op = if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit_op(op, span);
+        }
+    }
+
+    /// Emits the pops for the locals declared inside the innermost loop before
+    /// a `break` or `continue` jumps out of their scope. The locals stay known
+    /// to the compiler because the code after the jump still uses them.
+    fn discard_loop_locals(&mut self, span: Span) {
+        let state = self.current();
+        let loop_depth = state.loops.last().map_or(0, |state| state.scope_depth);
+        let ops: Vec<OpCode> = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > loop_depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit_op(op, span);
+        }
+    }
+
+    fn add_local(&mut self, name: String, span: Span) -> Result<()> {
+        let state = self.current();
+        if state.locals.len() == MAX_LOCALS {
+            return Err(Error::new(
+                span,
+                String::from("Too many local variables in function."),
+            ));
+        }
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name,
+            depth,
+            is_captured: false,
+        });
+        Ok(())
+    }
+
+    /// Locals live in the stack slot their value was pushed to, so declaring
+    /// one emits nothing. Globals return the constant holding their name.
+    fn declare_variable(&mut self, id: &Identifier) -> Result<Option<u16>> {
+        if self.current().scope_depth > 0 {
+            self.add_local(id.get_name(), id.get_span())?;
+            Ok(None)
+        } else {
+            self.name_constant(&id.get_name(), id.get_span()).map(Some)
+        }
+    }
+
+    fn define_variable(&mut self, id: &Identifier) -> Result<()> {
+        if let Some(name) = self.declare_variable(id)? {
+            self.emit_op(OpCode::DefineGlobal, id.get_span());
+            self.emit_u16(name, id.get_span());
+        }
+        Ok(())
+    }
+
+    fn get_variable(&mut self, name: &str, span: Span) -> Result<()> {
+        let (op, operand) = match self.resolve_variable(name, span)? {
+            VariableSlot::Local(slot) => (OpCode::GetLocal, slot as u16),
+            VariableSlot::Upvalue(index) => (OpCode::GetUpvalue, index as u16),
+            VariableSlot::Global(name) => (OpCode::GetGlobal, name),
+        };
+        self.emit_variable_op(op, operand, span);
+        Ok(())
+    }
+
+    fn resolve_variable(&mut self, name: &str, span: Span) -> Result<VariableSlot> {
+        let function = self.functions.len() - 1;
+        if let Some(slot) = self.functions[function].resolve_local(name) {
+            return Ok(VariableSlot::Local(slot));
+        }
+        if let Some(index) = self.resolve_upvalue(function, name, span)? {
+            return Ok(VariableSlot::Upvalue(index));
+        }
+        self.name_constant(name, span).map(VariableSlot::Global)
+    }
+
+    /// Looks for `name` in the functions enclosing `function`, threading an
+    /// upvalue through every function in between.
+    fn resolve_upvalue(&mut self, function: usize, name: &str, span: Span) -> Result<Option<u8>> {
+        if function == 0 {
+            return Ok(None);
+        }
+        let enclosing = function - 1;
+        if let Some(slot) = self.functions[enclosing].resolve_local(name) {
+            self.functions[enclosing].locals[slot as usize].is_captured = true;
+            return self.add_upvalue(function, slot, true, span).map(Some);
+        }
+        match self.resolve_upvalue(enclosing, name, span)? {
+            Some(index) => self.add_upvalue(function, index, false, span).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u8,
+        is_local: bool,
+        span: Span,
+    ) -> Result<u8> {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return Ok(existing as u8);
+        }
+        if upvalues.len() == MAX_UPVALUES {
+            return Err(Error::new(
+                span,
+                String::from("Too many closure variables in function."),
+            ));
+        }
+        upvalues.push(UpvalueRef { index, is_local });
+        Ok((upvalues.len() - 1) as u8)
+    }
+
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        let state = self.current();
+        state.span = span;
+        state.chunk.write(byte, span);
+    }
+
+    fn emit_op(&mut self, op: OpCode, span: Span) {
+        self.emit_byte(op as u8, span);
+    }
+
+    fn emit_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.emit_byte(byte, span);
+        }
+    }
+
+    /// Locals and upvalues take a one byte operand, globals a two byte name constant.
+    fn emit_variable_op(&mut self, op: OpCode, operand: u16, span: Span) {
+        self.emit_op(op, span);
+        match op {
+            OpCode::GetGlobal | OpCode::SetGlobal => self.emit_u16(operand, span),
+            _ => self.emit_byte(operand as u8, span),
+        }
+    }
+
+    fn emit_return(&mut self) {
+        let span = self.current().span;
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_op(OpCode::GetLocal, span);
+            self.emit_byte(0, span);
+        } else {
+            self.emit_op(OpCode::Nil, span);
+        }
+        self.emit_op(OpCode::Return, span);
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) -> Result<()> {
+        let constant = self.make_constant(value, span)?;
+        self.emit_op(OpCode::Constant, span);
+        self.emit_u16(constant, span);
+        Ok(())
+    }
+
+    fn make_constant(&mut self, value: Value, span: Span) -> Result<u16> {
+        let constant = self.current().chunk.add_constant(value);
+        u16::try_from(constant)
+            .map_err(|_| Error::new(span, String::from("Too many constants in one chunk.")))
+    }
+
+    /// Names of globals, properties and methods are stored once per chunk.
+    fn name_constant(&mut self, name: &str, span: Span) -> Result<u16> {
+        if let Some(constant) = self.current().names.get(name) {
+            return Ok(*constant);
+        }
+        let constant = self.make_constant(Value::String(Rc::from(name)), span)?;
+        self.current().names.insert(name.to_string(), constant);
+        Ok(constant)
+    }
+
+    fn count_operand(&self, count: usize, what: &str, span: Span) -> Result<u16> {
+        u16::try_from(count)
+            .map_err(|_| Error::new(span, format!("Too many {} in one literal.", what)))
+    }
+
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit_op(op, span);
+        self.emit_u16(u16::MAX, span);
+        self.current().chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) -> Result<()> {
+        let state = self.current();
+        let jump = state.chunk.code.len() - offset - 2;
+        let jump = u16::try_from(jump)
+            .map_err(|_| Error::new(state.span, String::from("Too much code to jump over.")))?;
+        state.chunk.code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) -> Result<()> {
+        self.emit_op(OpCode::Loop, span);
+        let offset = self.current().chunk.code.len() - loop_start + 2;
+        let offset = u16::try_from(offset)
+            .map_err(|_| Error::new(span, String::from("Loop body too large.")))?;
+        self.emit_u16(offset, span);
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Calling the natives the VM shares with the tree-walker, which work on
+//! `Literal`s. The arguments of a call are converted to literals and its
+//! result back to a value; the lists and maps among the arguments keep their
+//! identity, so a native changes the VM's own, as it would the tree-walker's.
+
+use super::Value;
+use crate::common::{Literal, RuntimeError, RuntimeResult};
+use crate::gc::{Heap, Trace, Tracer};
+use crate::runtime::{Arity, Interpreter, LoxCallable, LoxMap, NativeFunction};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type ListRef = Rc<RefCell<Vec<Value>>>;
+type MapRef = Rc<RefCell<LoxMap<Value>>>;
+type LiteralListRef = Rc<RefCell<Vec<Literal>>>;
+type LiteralMapRef = Rc<RefCell<LoxMap>>;
+
+/// A VM object without a `Literal` counterpart, such as a closure or an
+/// instance, on its way through a native function. It comes out as the
+/// object it went in as.
+#[derive(Debug)]
+pub struct Opaque(pub Value);
+
+impl Trace for Opaque {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+/// Natives can't call back into Lox, so only an embedder holding on to the
+/// literal could get here.
+impl LoxCallable for Opaque {
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        _args: Vec<Literal>,
+    ) -> RuntimeResult<Literal> {
+        Err(RuntimeError::new(
+            interpreter.call_span(),
+            format!("Can't call {} outside the virtual machine.", self.0),
+        ))
+    }
+
+    fn arity(&self) -> Arity {
+        Arity::AtLeast(0)
+    }
+
+    fn name(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn describe(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+/// The conversions of one native call. Every list and map of the arguments
+/// is converted once, by address, so aliases stay aliases and cycles end, and
+/// is written back once the native returns.
+#[derive(Default)]
+pub struct Conversion {
+    lists: Vec<(ListRef, LiteralListRef)>,
+    maps: Vec<(MapRef, LiteralMapRef)>,
+    /// The literals of the lists and maps converted so far, by the address
+    /// of the value they were converted from.
+    literals: HashMap<usize, Literal>,
+    /// The reverse, by the address of the literal.
+    values: HashMap<usize, Value>,
+}
+
+impl Conversion {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn to_literal(&mut self, value: &Value) -> Literal {
+        match value {
+            Value::String(s) => Literal::String(s.to_string()),
+            Value::Number(n) => Literal::Number(*n),
+            Value::Boolean(b) => Literal::Boolean(*b),
+            Value::Nil => Literal::Nil,
+            Value::HostNative(native) => Literal::Callable(native.clone()),
+            Value::List(list) => {
+                let address = Rc::as_ptr(list) as *const () as usize;
+                if let Some(literal) = self.literals.get(&address) {
+                    return literal.clone();
+                }
+                let literal = Rc::new(RefCell::new(vec![]));
+                self.remember(value, Literal::List(literal.clone()));
+                self.lists.push((list.clone(), literal.clone()));
+                let elements = list.borrow().iter().map(|v| self.to_literal(v)).collect();
+                *literal.borrow_mut() = elements;
+                Literal::List(literal)
+            }
+            Value::Map(map) => {
+                let address = Rc::as_ptr(map) as *const () as usize;
+                if let Some(literal) = self.literals.get(&address) {
+                    return literal.clone();
+                }
+                let literal = Rc::new(RefCell::new(LoxMap::new()));
+                self.remember(value, Literal::Map(literal.clone()));
+                self.maps.push((map.clone(), literal.clone()));
+                let mut entries = LoxMap::new();
+                for (key, value) in map.borrow().iter() {
+                    entries.insert(key.clone(), self.to_literal(value));
+                }
+                *literal.borrow_mut() = entries;
+                Literal::Map(literal)
+            }
+            _ => Literal::Callable(Rc::new(Opaque(value.clone()))),
+        }
+    }
+
+    /// Converts a literal a native returned or stored. Lists and maps that
+    /// didn't come from the VM are new, and tracked in `heap`. Fails on the
+    /// functions, classes and instances of the tree-walker.
+    pub fn from_literal(&mut self, literal: &Literal, heap: &mut Heap) -> Option<Value> {
+        match literal {
+            Literal::String(s) => Some(Value::String(Rc::from(s.as_str()))),
+            Literal::Number(n) => Some(Value::Number(*n)),
+            Literal::Boolean(b) => Some(Value::Boolean(*b)),
+            Literal::Nil => Some(Value::Nil),
+            Literal::Callable(callable) => {
+                let callable: Rc<dyn Any> = callable.clone();
+                match callable.downcast::<Opaque>() {
+                    Ok(opaque) => Some(opaque.0.clone()),
+                    Err(callable) => callable
+                        .downcast::<NativeFunction>()
+                        .ok()
+                        .map(Value::HostNative),
+                }
+            }
+            Literal::List(list) => {
+                let address = Rc::as_ptr(list) as *const () as usize;
+                if let Some(value) = self.values.get(&address) {
+                    return Some(value.clone());
+                }
+                let value = Rc::new(RefCell::new(vec![]));
+                heap.track(&value);
+                self.remember(&Value::List(value.clone()), literal.clone());
+                let elements = list
+                    .borrow()
+                    .iter()
+                    .map(|literal| self.from_literal(literal, heap))
+                    .collect::<Option<Vec<Value>>>()?;
+                *value.borrow_mut() = elements;
+                Some(Value::List(value))
+            }
+            Literal::Map(map) => {
+                let address = Rc::as_ptr(map) as *const () as usize;
+                if let Some(value) = self.values.get(&address) {
+                    return Some(value.clone());
+                }
+                let value = Rc::new(RefCell::new(LoxMap::new()));
+                heap.track(&value);
+                self.remember(&Value::Map(value.clone()), literal.clone());
+                let mut entries = LoxMap::new();
+                for (key, literal) in map.borrow().iter() {
+                    entries.insert(key.clone(), self.from_literal(literal, heap)?);
+                }
+                *value.borrow_mut() = entries;
+                Some(Value::Map(value))
+            }
+            Literal::Class(_) | Literal::Instance(_) => None,
+        }
+    }
+
+    /// Writes the changes the native made to the lists and maps among its
+    /// arguments back to the VM's. Fails with the first element that can't
+    /// be converted, leaving that list or map as it was.
+    pub fn write_back(&mut self, heap: &mut Heap) -> Result<(), Literal> {
+        for i in 0..self.lists.len() {
+            let (list, literal) = self.lists[i].clone();
+            let mut elements = Vec::with_capacity(literal.borrow().len());
+            for element in literal.borrow().iter() {
+                let value = self
+                    .from_literal(element, heap)
+                    .ok_or_else(|| element.clone())?;
+                elements.push(value);
+            }
+            *list.borrow_mut() = elements;
+        }
+        for i in 0..self.maps.len() {
+            let (map, literal) = self.maps[i].clone();
+            let mut entries = LoxMap::new();
+            for (key, element) in literal.borrow().iter() {
+                let value = self
+                    .from_literal(element, heap)
+                    .ok_or_else(|| element.clone())?;
+                entries.insert(key.clone(), value);
+            }
+            *map.borrow_mut() = entries;
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, value: &Value, literal: Literal) {
+        let (value_address, literal_address) = match (value, &literal) {
+            (Value::List(value), Literal::List(literal)) => (
+                Rc::as_ptr(value) as *const () as usize,
+                Rc::as_ptr(literal) as *const () as usize,
+            ),
+            (Value::Map(value), Literal::Map(literal)) => (
+                Rc::as_ptr(value) as *const () as usize,
+                Rc::as_ptr(literal) as *const () as usize,
+            ),
+            _ => return,
+        };
+        self.values.insert(literal_address, value.clone());
+        self.literals.insert(value_address, literal);
+    }
+}
@@ -0,0 +1,1183 @@
+use super::chunk::{IMPORT_HAS_NAME, SLICE_HAS_END, SLICE_HAS_START};
+use super::host::Conversion;
+use super::natives::natives;
+use super::{
+    BoundMethod, BoundNative, Class, Closure, Compiler, FunctionProto, Globals, Instance, OpCode,
+    Upvalue, Value,
+};
+use crate::common::{Literal, RuntimeError, RuntimeResult, Span, StackFrame};
+use crate::gc::{GcStats, Heap, Trace};
+use crate::module::{Import, Modules};
+use crate::runtime::builtins::{self, SharedOutput};
+use crate::runtime::{
+    list, Args, LoxCallable, LoxMap, NativeClass, NativeError, NativeFunction, NativeModule,
+    NativeResult,
+};
+use crate::sandbox::{Budget, Limit, Sandbox};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// A function call that is currently executing. `base` is the stack slot
+/// holding the callee, followed by the arguments and locals of the call.
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    base: usize,
+    /// Set when the frame runs `init` on behalf of a class being called, which
+    /// the tree-walker reports under the class's name.
+    class: Option<Rc<Class>>,
+    /// Set when the frame runs the top level of an imported module.
+    import: Option<PendingImport>,
+}
+
+/// Where execution continues when an error is raised while a `try` statement
+/// runs: the frame and stack height it was entered at and the address of its
+/// handler.
+struct Handler {
+    frame: usize,
+    stack: usize,
+    /// The number of errors saved for `finally` blocks at that point.
+    saved: usize,
+    ip: usize,
+    /// Whether the handler runs a `finally` block and rethrows the error,
+    /// rather than receiving it as a value.
+    finally: bool,
+}
+
+/// What to do once an imported module has run: the path it was imported as,
+/// and the name to import from it, if only one.
+struct PendingImport {
+    path: Rc<str>,
+    name: Option<Rc<str>>,
+}
+
+impl CallFrame {
+    fn name(&self) -> String {
+        match &self.class {
+            Some(class) => class.name.clone(),
+            None => self.closure.function.name.clone(),
+        }
+    }
+
+    fn span(&self) -> Span {
+        self.closure.function.chunk.span(self.ip.saturating_sub(1))
+    }
+
+    fn line(&self) -> usize {
+        self.span().line
+    }
+}
+
+/// A stack-based virtual machine executing chunks produced by the `Compiler`.
+/// Globals outlive a single `interpret` call so that REPL lines can build on
+/// each other.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: Globals,
+    /// The globals defined by the host, which every module starts with.
+    prelude: HashMap<Rc<str>, Value>,
+    /// The globals of every module imported so far.
+    modules: Modules<Globals>,
+    handlers: Vec<Handler>,
+    /// The errors `finally` blocks rethrow once they have run, along with
+    /// the values thrown for them.
+    saved: Vec<(RuntimeError, Option<Value>)>,
+    /// The value of the `throw` instruction whose error is unwinding, until a
+    /// handler receives it.
+    thrown: Option<Value>,
+    /// The class of the values handlers receive for runtime errors.
+    error_class: Rc<Class>,
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    output: Box<dyn Write>,
+    /// Where `eprint` writes.
+    error_output: SharedOutput,
+    /// Tracks the upvalues, instances, lists and maps the program creates,
+    /// which are what cycles are made of.
+    heap: Heap,
+    /// What is left of the limits of the sandbox in the current run.
+    budget: Budget,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::sandboxed(&Sandbox::unrestricted())
+    }
+
+    /// A machine with only the native modules `sandbox` allows, running
+    /// programs within its limits.
+    pub fn sandboxed(sandbox: &Sandbox) -> Self {
+        let mut prelude = HashMap::new();
+        let error_class = Rc::new(Class {
+            name: String::from("Error"),
+            superclass: RefCell::new(None),
+            methods: RefCell::new(HashMap::new()),
+            native_methods: RefCell::new(HashMap::new()),
+        });
+        prelude.insert(Rc::from("Error"), Value::Class(error_class.clone()));
+        let error_output: SharedOutput = Rc::new(RefCell::new(Box::new(io::stderr())));
+        let mut vm = Self {
+            stack: vec![],
+            frames: vec![],
+            globals: Rc::new(RefCell::new(prelude.clone())),
+            prelude,
+            modules: Modules::new(),
+            handlers: vec![],
+            saved: vec![],
+            thrown: None,
+            error_class,
+            open_upvalues: vec![],
+            output: Box::new(io::stdout()),
+            error_output: error_output.clone(),
+            heap: Heap::new(),
+            budget: sandbox.budget(),
+        };
+        // The modules of the tree-walker are shared, their natives called
+        // through `call_host`. Their constants are numbers, which can't fail
+        // to convert.
+        let modules = [
+            builtins::core(),
+            builtins::time(),
+            builtins::math(),
+            builtins::string(),
+            builtins::io(error_output),
+        ];
+        for module in modules {
+            if sandbox.allows_module(module.name()) {
+                let _ = vm.define_module(module);
+            }
+        }
+        if sandbox.allows_module("core") {
+            for native in natives() {
+                vm.set_global(native.name, Value::Native(Rc::new(native)));
+            }
+        }
+        if !sandbox.allows_imports() {
+            vm.modules.forbid_imports();
+        }
+        vm
+    }
+
+    /// Sends the output of `print` statements to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Sends what `eprint` writes to `output` instead of stderr.
+    pub fn set_error_output(&mut self, output: Box<dyn Write>) {
+        *self.error_output.borrow_mut() = output;
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.globals.borrow().get(name).cloned()
+    }
+
+    /// Defines a global variable. Lists and maps in `value` are tracked by the
+    /// collector, so they must not have been handed to the VM before.
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.track_converted(&value);
+        self.prelude.insert(Rc::from(name), value.clone());
+        self.globals.borrow_mut().insert(Rc::from(name), value);
+    }
+
+    pub fn modules(&mut self) -> &mut Modules<Globals> {
+        &mut self.modules
+    }
+
+    /// Defines a global function implemented by a Rust closure.
+    pub fn define_native(&mut self, native: NativeFunction) {
+        let name = native.name();
+        self.set_global(&name, Value::HostNative(Rc::new(native)));
+    }
+
+    /// Defines every function and constant of `module` as a global. Fails
+    /// with the first constant the VM can't represent, leaving the ones
+    /// before it defined.
+    pub fn define_module(&mut self, module: NativeModule) -> Result<(), Literal> {
+        let (functions, constants) = module.into_parts();
+        for (name, value) in constants {
+            let value = Value::from_literal(&value).ok_or(value)?;
+            self.set_global(&name, value);
+        }
+        for function in functions {
+            self.define_native(function);
+        }
+        Ok(())
+    }
+
+    /// Defines a global class whose methods are implemented in Rust.
+    pub fn define_class(&mut self, class: NativeClass) {
+        let (name, methods) = class.into_parts();
+        let class = Class {
+            name: name.clone(),
+            superclass: RefCell::new(None),
+            methods: RefCell::new(HashMap::new()),
+            native_methods: RefCell::new(methods),
+        };
+        self.set_global(&name, Value::Class(Rc::new(class)));
+    }
+
+    /// Frees the objects only reachable through cycles and returns how many
+    /// there were.
+    pub fn collect_garbage(&mut self) -> usize {
+        self.heap.collect()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Runs a compiled script and returns the value it produces, which is the
+    /// value of its final statement when that is an expression statement.
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> RuntimeResult<Value> {
+        self.budget.start();
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: vec![],
+            globals: self.globals.clone(),
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        let result = self.call(closure, 0, None).and_then(|_| self.run());
+        if result.is_err() {
+            // Leave the machine usable for the next REPL line.
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.modules.cancel_all();
+            self.handlers.clear();
+            self.saved.clear();
+            self.thrown = None;
+        }
+        result
+    }
+
+    fn run(&mut self) -> RuntimeResult<Value> {
+        loop {
+            match self.execute() {
+                Ok(value) => return Ok(value),
+                Err(err) => self.unwind(err)?,
+            }
+        }
+    }
+
+    /// Continues at the handler of the innermost `try` statement running, or
+    /// gives the error back if there is none.
+    fn unwind(&mut self, err: RuntimeError) -> RuntimeResult<()> {
+        // Running out of a limit of the sandbox stops the program without
+        // running anything more.
+        if err.limit.is_some() {
+            return Err(err);
+        }
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => return Err(err),
+        };
+        self.frames.truncate(handler.frame + 1);
+        self.close_upvalues(handler.stack);
+        self.stack.truncate(handler.stack);
+        self.saved.truncate(handler.saved);
+        self.frame_mut().ip = handler.ip;
+        let thrown = if err.thrown { self.thrown.take() } else { None };
+        if handler.finally {
+            self.saved.push((err, thrown));
+        } else {
+            let value = match thrown {
+                Some(value) => value,
+                None => self.error_value(&err),
+            };
+            self.stack.push(value);
+        }
+        Ok(())
+    }
+
+    /// An `Error` instance with the message and line of a runtime error.
+    fn error_value(&mut self, err: &RuntimeError) -> Value {
+        let mut fields = HashMap::new();
+        fields.insert(
+            String::from("message"),
+            Value::String(Rc::from(err.message.as_str())),
+        );
+        fields.insert(String::from("line"), Value::Number(err.line as f64));
+        Value::Instance(self.track(Rc::new(Instance {
+            class: self.error_class.clone(),
+            fields: RefCell::new(fields),
+            host_data: RefCell::new(None),
+        })))
+    }
+
+    /// Runs instructions until the script returns or an error is raised.
+    fn execute(&mut self) -> RuntimeResult<Value> {
+        loop {
+            if let Err(limit) = self.budget.step(&mut self.heap) {
+                return Err(self.limit_error(limit));
+            }
+            let op = self.read_byte();
+            let op = match OpCode::from_byte(op) {
+                Some(op) => op,
+                None => return Err(self.error(format!("Unknown opcode {}.", op))),
+            };
+            match op {
+                OpCode::Constant => {
+                    let value = self.read_constant();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::Dup => self.stack.push(self.peek(0).clone()),
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    let value = self.frame().closure.globals.borrow().get(&name).cloned();
+                    match value {
+                        Some(value) => self.stack.push(value),
+                        None => return Err(self.undefined_variable(&name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.frame()
+                        .closure
+                        .globals
+                        .borrow_mut()
+                        .insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek(0).clone();
+                    let globals = self.frame().closure.globals.clone();
+                    let previous = globals
+                        .borrow_mut()
+                        .get_mut(&name)
+                        .map(|global| std::mem::replace(global, value));
+                    if previous.is_none() {
+                        return Err(self.undefined_variable(&name));
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let instance = match self.pop() {
+                        Value::Instance(instance) => instance,
+                        _ => return Err(self.error_str("Only instances have properties.")),
+                    };
+                    let field = instance.fields.borrow().get(&*name).cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => match bind_method(&instance.class, &name, &instance) {
+                            Some(method) => method,
+                            None => return Err(self.error_str("Getter not found on instance.")),
+                        },
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    match self.pop() {
+                        Value::Instance(instance) => {
+                            instance
+                                .fields
+                                .borrow_mut()
+                                .insert(name.to_string(), value.clone());
+                            self.stack.push(value);
+                        }
+                        _ => return Err(self.error_str("Only instances have properties.")),
+                    }
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_name();
+                    let superclass = self.pop();
+                    let receiver = self.pop();
+                    let method = match (&superclass, &receiver) {
+                        (Value::Class(superclass), Value::Instance(instance)) => {
+                            bind_method(superclass, &name, instance)
+                        }
+                        _ => None,
+                    };
+                    match method {
+                        Some(method) => self.stack.push(method),
+                        None => return Err(self.error(format!("Undefined property '{}'.", name))),
+                    }
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let equal = match (&left, &right) {
+                        (Value::Number(a), Value::Number(b)) => a == b,
+                        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+                        (Value::String(a), Value::String(b)) => a == b,
+                        _ => {
+                            return Err(
+                                self.error_str("Operands must be strings, numbers or booleans.")
+                            )
+                        }
+                    };
+                    self.stack
+                        .push(Value::Boolean(equal == (op == OpCode::Equal)));
+                }
+                OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let (a, b) = match (left, right) {
+                        (Value::Number(a), Value::Number(b)) => (a, b),
+                        _ => return Err(self.error_str("Operands must be numbers.")),
+                    };
+                    let value = match op {
+                        OpCode::Greater => Value::Boolean(a > b),
+                        OpCode::GreaterEqual => Value::Boolean(a >= b),
+                        OpCode::Less => Value::Boolean(a < b),
+                        OpCode::LessEqual => Value::Boolean(a <= b),
+                        OpCode::Subtract => Value::Number(a - b),
+                        OpCode::Multiply => Value::Number(a * b),
+                        _ if b == 0.0 => return Err(self.error_str("Cannot divide by zero.")),
+                        _ => Value::Number(a / b),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Add => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let value = match (left, right) {
+                        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (Value::String(a), Value::String(b)) => {
+                            self.check_length(a.len() + b.len())?;
+                            Value::String(Rc::from(format!("{}{}", a, b)))
+                        }
+                        (Value::Number(n), Value::String(s)) => {
+                            self.check_length(s.len())?;
+                            Value::String(Rc::from(format!("{}{}", n, s)))
+                        }
+                        (Value::String(s), Value::Number(n)) => {
+                            self.check_length(s.len())?;
+                            Value::String(Rc::from(format!("{}{}", s, n)))
+                        }
+                        _ => return Err(self.error_str("Operands must be numbers or strings.")),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Comma => {
+                    let right = self.pop();
+                    self.pop();
+                    match right {
+                        Value::Number(_) | Value::String(_) | Value::Boolean(_) => {
+                            self.stack.push(right)
+                        }
+                        _ => {
+                            return Err(
+                                self.error_str("Operands must be strings, numbers or booleans.")
+                            )
+                        }
+                    }
+                }
+                OpCode::Not => match self.pop() {
+                    Value::Boolean(b) => self.stack.push(Value::Boolean(!b)),
+                    _ => return Err(self.error_str("Operand must be a boolean.")),
+                },
+                OpCode::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => return Err(self.error_str("Operand must be a number.")),
+                },
+                OpCode::Ternary => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    match self.pop() {
+                        Value::Boolean(true) => self.stack.push(left),
+                        Value::Boolean(false) => self.stack.push(right),
+                        _ => return Err(self.error_str("Expression must evaluate to boolean")),
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    writeln!(self.output, "{}", value)
+                        .map_err(|_| self.error_str("Can't write to the output."))?;
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let argc = self.read_byte() as usize;
+                    self.call_value(argc)?;
+                }
+                OpCode::Closure => {
+                    let function = match self.read_constant() {
+                        Value::Function(function) => function,
+                        _ => return Err(self.error_str("Closure operand must be a function.")),
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        if is_local {
+                            let slot = self.frame().base + index;
+                            upvalues.push(self.capture_upvalue(slot));
+                        } else {
+                            upvalues.push(self.frame().closure.upvalues[index].clone());
+                        }
+                    }
+                    let globals = self.frame().closure.globals.clone();
+                    self.stack.push(Value::Closure(Rc::new(Closure {
+                        function,
+                        upvalues,
+                        globals,
+                    })));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if let Some(import) = frame.import {
+                        let globals = frame.closure.globals.clone();
+                        self.modules.finish(globals.clone());
+                        self.bind_import(&globals, &import)?;
+                        continue;
+                    }
+                    if self.frames.is_empty() {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_name();
+                    self.stack.push(Value::Class(Rc::new(Class {
+                        name: name.to_string(),
+                        superclass: RefCell::new(None),
+                        methods: RefCell::new(HashMap::new()),
+                        native_methods: RefCell::new(HashMap::new()),
+                    })));
+                }
+                OpCode::Inherit => {
+                    // Methods are copied down when the class is created, which
+                    // is equivalent to looking them up the superclass chain
+                    // because classes can't change afterwards.
+                    match (self.peek(1), self.peek(0)) {
+                        (Value::Class(superclass), Value::Class(subclass)) => {
+                            let methods = superclass.methods.borrow().clone();
+                            subclass.methods.borrow_mut().extend(methods);
+                            let native_methods = superclass.native_methods.borrow().clone();
+                            subclass.native_methods.borrow_mut().extend(native_methods);
+                            *subclass.superclass.borrow_mut() = Some(superclass.clone());
+                        }
+                        _ => return Err(self.error_str("Superclass must be a class.")),
+                    }
+                    self.pop();
+                }
+                OpCode::Method => {
+                    let name = self.read_name();
+                    let method = match self.pop() {
+                        Value::Closure(closure) => closure,
+                        _ => return Err(self.error_str("Method must be a function.")),
+                    };
+                    if let Value::Class(class) = self.peek(0) {
+                        class.methods.borrow_mut().insert(name.to_string(), method);
+                    }
+                }
+                OpCode::BuildList => {
+                    let count = self.read_u16() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - count);
+                    let list = self.track(Rc::new(RefCell::new(elements)));
+                    self.stack.push(Value::List(list));
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_u16() as usize;
+                    let entries = self.stack.split_off(self.stack.len() - 2 * count);
+                    let mut map = LoxMap::new();
+                    let span = self.frame().span();
+                    for entry in entries.chunks(2) {
+                        map.insert(entry[0].to_key(span)?, entry[1].clone());
+                    }
+                    let map = self.track(Rc::new(RefCell::new(map)));
+                    self.stack.push(Value::Map(map));
+                }
+                OpCode::IndexGet => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let span = self.frame().span();
+                    let value = match object {
+                        Value::List(list) => {
+                            let list = list.borrow();
+                            let position =
+                                list::element_index(index.as_number(), list.len(), span)?;
+                            list[position].clone()
+                        }
+                        Value::Map(map) => {
+                            let key = index.to_key(span)?;
+                            let value = map.borrow().get(&key);
+                            match value {
+                                Some(value) => value,
+                                None => {
+                                    return Err(
+                                        self.error(format!("Key {} not found in map.", index))
+                                    )
+                                }
+                            }
+                        }
+                        _ => return Err(self.error_str("Only lists and maps can be indexed.")),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::IndexSet => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    let span = self.frame().span();
+                    match object {
+                        Value::List(list) => {
+                            let mut list = list.borrow_mut();
+                            let position =
+                                list::element_index(index.as_number(), list.len(), span)?;
+                            list[position] = value.clone();
+                        }
+                        Value::Map(map) => {
+                            let key = index.to_key(span)?;
+                            let mut map = map.borrow_mut();
+                            if !map.contains_key(&key) {
+                                self.check_length(map.len() + 1)?;
+                            }
+                            map.insert(key, value.clone());
+                        }
+                        _ => return Err(self.error_str("Only lists and maps can be indexed.")),
+                    }
+                    self.stack.push(value);
+                }
+                OpCode::Slice => {
+                    let flags = self.read_byte();
+                    let end = if flags & SLICE_HAS_END != 0 {
+                        Some(self.pop())
+                    } else {
+                        None
+                    };
+                    let start = if flags & SLICE_HAS_START != 0 {
+                        Some(self.pop())
+                    } else {
+                        None
+                    };
+                    let span = self.frame().span();
+                    let list = match self.pop() {
+                        Value::List(list) => list,
+                        _ => return Err(self.error_str("Only lists can be sliced.")),
+                    };
+                    let list = list.borrow();
+                    let start = match start {
+                        Some(start) => list::boundary_index(start.as_number(), list.len(), span)?,
+                        None => 0,
+                    };
+                    let end = match end {
+                        Some(end) => list::boundary_index(end.as_number(), list.len(), span)?,
+                        None => list.len(),
+                    };
+                    if start > end {
+                        return Err(self
+                            .error(format!("Slice start {} is after slice end {}.", start, end)));
+                    }
+                    let slice = Rc::new(RefCell::new(list[start..end].to_vec()));
+                    let slice = self.track(slice);
+                    self.stack.push(Value::List(slice));
+                }
+                OpCode::Import => self.import()?,
+                OpCode::Try | OpCode::TryFinally => {
+                    let offset = self.read_u16() as usize;
+                    self.handlers.push(Handler {
+                        frame: self.frames.len() - 1,
+                        stack: self.stack.len(),
+                        saved: self.saved.len(),
+                        ip: self.frame().ip + offset,
+                        finally: op == OpCode::TryFinally,
+                    });
+                }
+                OpCode::EndTry => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    let message = match &value {
+                        Value::Instance(instance) => {
+                            match instance.fields.borrow().get("message") {
+                                Some(Value::String(message)) => Some(message.to_string()),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    let message =
+                        message.unwrap_or_else(|| format!("Uncaught exception: {}.", value));
+                    let mut err = self.error(message);
+                    err.thrown = true;
+                    self.thrown = Some(value);
+                    return Err(err);
+                }
+                OpCode::Rethrow => match self.saved.pop() {
+                    Some((err, thrown)) => {
+                        self.thrown = thrown;
+                        return Err(err);
+                    }
+                    None => return Err(self.error_str("No error to rethrow.")),
+                },
+                OpCode::DiscardError => {
+                    self.saved.pop();
+                }
+            }
+        }
+    }
+
+    /// Binds the names of the module an `Import` instruction names, after
+    /// starting to run it unless it ran before. The path operand comes first,
+    /// so that errors finding the module point at the path.
+    fn import(&mut self) -> RuntimeResult<()> {
+        let path = self.read_name();
+        let span = self.frame().span();
+        let import = self.modules.begin(&path, span)?;
+        let name = match self.read_byte() & IMPORT_HAS_NAME {
+            0 => None,
+            _ => Some(self.read_name()),
+        };
+        let import_frame = PendingImport { path, name };
+        let statements = match import {
+            Import::Cached(globals) => return self.bind_import(&globals, &import_frame),
+            Import::Load(statements) => statements,
+        };
+        let function = match Compiler::compile(&statements, false) {
+            Ok(function) => function,
+            Err(err) => {
+                self.modules.cancel();
+                let report = err.render(&self.modules.sources().borrow());
+                return Err(RuntimeError::new(
+                    span,
+                    format!("Module '{}' has errors:\n{}", import_frame.path, report),
+                ));
+            }
+        };
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: vec![],
+            globals: Rc::new(RefCell::new(self.prelude.clone())),
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.call(closure, 0, None)?;
+        self.frame_mut().import = Some(import_frame);
+        Ok(())
+    }
+
+    /// Defines the global the import names, or all globals of the module but
+    /// the prelude, in the globals of the importing frame.
+    fn bind_import(&mut self, module: &Globals, import: &PendingImport) -> RuntimeResult<()> {
+        let module = module.borrow();
+        let mut globals = self.frame().closure.globals.borrow_mut();
+        match &import.name {
+            Some(name) => match module.get(name) {
+                Some(value) => {
+                    globals.insert(name.clone(), value.clone());
+                }
+                None => {
+                    drop(globals);
+                    return Err(self.error(format!(
+                        "Module '{}' doesn't define '{}'.",
+                        import.path, name
+                    )));
+                }
+            },
+            None => {
+                for (name, value) in module.iter() {
+                    if !self.prelude.contains_key(name) {
+                        globals.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn call_value(&mut self, argc: usize) -> RuntimeResult<()> {
+        let callee_slot = self.stack.len() - argc - 1;
+        match self.stack[callee_slot].clone() {
+            Value::Closure(closure) => self.call(closure, argc, None),
+            Value::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), argc, None)
+            }
+            Value::Class(class) => {
+                let instance = self.track(Rc::new(Instance {
+                    class: class.clone(),
+                    fields: RefCell::new(HashMap::new()),
+                    host_data: RefCell::new(None),
+                }));
+                self.stack[callee_slot] = Value::Instance(instance.clone());
+                let initializer = class.methods.borrow().get("init").cloned();
+                let native_initializer = class.native_methods.borrow().get("init").cloned();
+                match (initializer, native_initializer) {
+                    (Some(initializer), _) => self.call(initializer, argc, Some(class)),
+                    (None, Some(initializer)) => {
+                        if !initializer.arity().accepts(argc) {
+                            return Err(self.arity_error(initializer.arity(), argc));
+                        }
+                        let result = self
+                            .call_host(callee_slot, |args| {
+                                initializer.invoke(&instance.host_data, args)
+                            })
+                            .map(|_| Value::Instance(instance.clone()));
+                        self.finish_native(&class.name, callee_slot, result)
+                    }
+                    (None, None) if argc != 0 => Err(self.arity_error(0, argc)),
+                    (None, None) => Ok(()),
+                }
+            }
+            Value::Native(native) => {
+                if argc != native.arity {
+                    return Err(self.arity_error(native.arity, argc));
+                }
+                let span = self.frame().span();
+                let result = (native.function)(
+                    &mut self.heap,
+                    &self.budget,
+                    &self.stack[callee_slot + 1..],
+                    span,
+                );
+                self.finish_native(native.name, callee_slot, result)
+            }
+            Value::HostNative(native) => {
+                if !native.arity().accepts(argc) {
+                    return Err(self.arity_error(native.arity(), argc));
+                }
+                let result = self.call_host(callee_slot, |args| native.invoke(args));
+                self.finish_native(&native.name(), callee_slot, result)
+            }
+            Value::BoundNative(bound) => {
+                let method = &bound.method;
+                if !method.arity().accepts(argc) {
+                    return Err(self.arity_error(method.arity(), argc));
+                }
+                let result = self
+                    .call_host(callee_slot, |args| {
+                        method.invoke(&bound.receiver.host_data, args)
+                    })
+                    .map(|value| match method.name().as_str() {
+                        // Like initializers written in Lox, `init` returns the instance.
+                        "init" => Value::Instance(bound.receiver.clone()),
+                        _ => value,
+                    });
+                self.finish_native(&method.name(), callee_slot, result)
+            }
+            _ => Err(self.error_str("Can only call functions and classes.")),
+        }
+    }
+
+    /// Calls a native function or method shared with the tree-walker, or
+    /// defined by the embedder. Those work on `Literal`s, so their arguments
+    /// and result are converted. Lists and maps are copied both ways, which
+    /// takes time linear in their size, but changes to them are kept.
+    fn call_host(
+        &mut self,
+        callee_slot: usize,
+        invoke: impl FnOnce(&Args) -> NativeResult<Literal>,
+    ) -> RuntimeResult<Value> {
+        let span = self.frame().span();
+        let mut conversion = Conversion::new();
+        let args = self.stack[callee_slot + 1..]
+            .iter()
+            .map(|value| conversion.to_literal(value))
+            .collect::<Vec<Literal>>();
+        let result = invoke(&Args::new(&args, span).with_max_length(self.budget.max_length()));
+        conversion.write_back(&mut self.heap).map_err(|literal| {
+            RuntimeError::new(
+                span,
+                format!(
+                    "Native function stored {}, which the VM can't hold.",
+                    literal
+                ),
+            )
+        })?;
+        let result = result.map_err(|err| match err {
+            NativeError::Limit(limit) => self.budget.error(limit, span),
+            err => RuntimeError::new(span, err.to_string()),
+        })?;
+        conversion
+            .from_literal(&result, &mut self.heap)
+            .ok_or_else(|| {
+                RuntimeError::new(
+                    span,
+                    format!(
+                        "Native function returned {}, which the VM can't hold.",
+                        result
+                    ),
+                )
+            })
+    }
+
+    /// Replaces the callee and arguments with the result of a native call, or
+    /// adds the native to the trace of its error.
+    fn finish_native(
+        &mut self,
+        name: &str,
+        callee_slot: usize,
+        result: RuntimeResult<Value>,
+    ) -> RuntimeResult<()> {
+        match result {
+            Ok(value) => {
+                self.stack.truncate(callee_slot);
+                self.stack.push(value);
+                Ok(())
+            }
+            Err(mut err) => {
+                err.trace = vec![StackFrame {
+                    function: name.to_string(),
+                    line: err.line,
+                }];
+                err.trace.extend(self.stack_trace());
+                Err(err)
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        closure: Rc<Closure>,
+        argc: usize,
+        class: Option<Rc<Class>>,
+    ) -> RuntimeResult<()> {
+        if argc != closure.function.arity {
+            return Err(self.arity_error(closure.function.arity, argc));
+        }
+        // The frame of the script doesn't count as a call. The depth is
+        // limited even outside of sandboxes, by `MAX_CALL_DEPTH`.
+        if let Err(limit) = self.budget.enter_call(self.frames.len()) {
+            return Err(self.limit_error(limit));
+        }
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - argc - 1,
+            class,
+            import: None,
+        });
+        Ok(())
+    }
+
+    fn track<T: Trace + 'static>(&mut self, object: Rc<T>) -> Rc<T> {
+        self.heap.track(&object);
+        object
+    }
+
+    /// Tracks the lists and maps of a value `Value::from_literal` created,
+    /// which are all new.
+    fn track_converted(&mut self, value: &Value) {
+        match value {
+            Value::List(list) => {
+                self.heap.track(list);
+                for element in list.borrow().iter() {
+                    self.track_converted(element);
+                }
+            }
+            Value::Map(map) => {
+                self.heap.track(map);
+                for (_, value) in map.borrow().iter() {
+                    self.track_converted(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        for upvalue in &self.open_upvalues {
+            if let Upvalue::Open(open) = *upvalue.borrow() {
+                if open == slot {
+                    return upvalue.clone();
+                }
+            }
+        }
+        let upvalue = self.track(Rc::new(RefCell::new(Upvalue::Open(slot))));
+        self.open_upvalues.push(upvalue.clone());
+        upvalue
+    }
+
+    /// Moves the values of captured variables living at or above `from` off
+    /// the stack and into their upvalues.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_u16() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::String(name) => name,
+            _ => Rc::from(""),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or(Value::Nil)
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn error_str(&self, message: &str) -> RuntimeError {
+        self.error(String::from(message))
+    }
+
+    /// Raises an error at the instruction being executed. Errors at the top
+    /// level of a script or module carry no trace, like in the tree-walker.
+    fn error(&self, message: String) -> RuntimeError {
+        let mut err = RuntimeError::new(self.frame().span(), message);
+        if self.frames.len() > self.script_frame() + 1 {
+            err.trace = self.stack_trace();
+        }
+        err
+    }
+
+    fn limit_error(&self, limit: Limit) -> RuntimeError {
+        let mut err = self.error(self.budget.message(limit));
+        err.limit = Some(limit);
+        err
+    }
+
+    /// Checks a string of `len` bytes, or a list or map of `len` elements,
+    /// against the allocation limit before building it.
+    fn check_length(&self, len: usize) -> RuntimeResult<()> {
+        self.budget
+            .check_length(len)
+            .map_err(|limit| self.limit_error(limit))
+    }
+
+    fn arity_error(&self, arity: impl Display, argc: usize) -> RuntimeError {
+        self.error(format!("Expected {} arguments but got {}.", arity, argc))
+    }
+
+    fn undefined_variable(&self, name: &str) -> RuntimeError {
+        self.error(format!("Undefined {} variable.", name))
+    }
+
+    /// The frame running the top level of the innermost module, or of the
+    /// script. Modules only run at the top level, so traces stop there, like
+    /// in the tree-walker.
+    fn script_frame(&self) -> usize {
+        self.frames
+            .iter()
+            .rposition(|frame| frame.import.is_some())
+            .unwrap_or(0)
+    }
+
+    fn stack_trace(&self) -> Vec<StackFrame> {
+        self.frames[self.script_frame()..]
+            .iter()
+            .rev()
+            .map(|frame| StackFrame {
+                function: frame.name(),
+                line: frame.line(),
+            })
+            .collect()
+    }
+}
+
+/// Looks `name` up among the methods of `class` and binds it to `instance`.
+fn bind_method(class: &Class, name: &str, instance: &Rc<Instance>) -> Option<Value> {
+    if let Some(method) = class.methods.borrow().get(name) {
+        return Some(Value::BoundMethod(Rc::new(BoundMethod {
+            receiver: Value::Instance(instance.clone()),
+            method: method.clone(),
+        })));
+    }
+    class.native_methods.borrow().get(name).map(|method| {
+        Value::BoundNative(Rc::new(BoundNative {
+            receiver: instance.clone(),
+            method: method.clone(),
+        }))
+    })
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
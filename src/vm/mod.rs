@@ -0,0 +1,14 @@
+pub mod chunk;
+pub mod compiler;
+pub mod host;
+pub mod machine;
+pub mod natives;
+pub mod value;
+
+pub use chunk::{Chunk, OpCode};
+pub use compiler::Compiler;
+pub use machine::Vm;
+pub use value::{
+    BoundMethod, BoundNative, Class, Closure, FunctionProto, Globals, Instance, Native, Upvalue,
+    Value,
+};
@@ -0,0 +1,229 @@
+use super::{Native, Value};
+use crate::common::{RuntimeError, RuntimeResult, Span};
+use crate::gc::Heap;
+use crate::runtime::{list, LoxMap, MapKey};
+use crate::sandbox::Budget;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// The natives of the core module that the VM doesn't share with the
+/// tree-walker, which replace their counterparts in `runtime::builtins::core`.
+/// The list and map functions would copy their whole argument each call
+/// through `call_host`, making loops over lists quadratic; `type` and
+/// `instanceof` look into the VM's own objects, which don't convert to
+/// `Literal`s; and `gc` collects the VM's heap.
+pub fn natives() -> Vec<Native> {
+    vec![
+        Native {
+            name: "len",
+            arity: 1,
+            function: len,
+        },
+        Native {
+            name: "push",
+            arity: 2,
+            function: push,
+        },
+        Native {
+            name: "pop",
+            arity: 1,
+            function: pop,
+        },
+        Native {
+            name: "insert",
+            arity: 3,
+            function: insert,
+        },
+        Native {
+            name: "remove",
+            arity: 2,
+            function: remove,
+        },
+        Native {
+            name: "keys",
+            arity: 1,
+            function: keys,
+        },
+        Native {
+            name: "values",
+            arity: 1,
+            function: values,
+        },
+        Native {
+            name: "has",
+            arity: 2,
+            function: has,
+        },
+        Native {
+            name: "delete",
+            arity: 2,
+            function: delete,
+        },
+        Native {
+            name: "type",
+            arity: 1,
+            function: type_name,
+        },
+        Native {
+            name: "instanceof",
+            arity: 2,
+            function: instanceof,
+        },
+        Native {
+            name: "gc",
+            arity: 0,
+            function: gc,
+        },
+    ]
+}
+
+fn list_argument(value: &Value, span: Span) -> RuntimeResult<Rc<RefCell<Vec<Value>>>> {
+    match value {
+        Value::List(list) => Ok(list.clone()),
+        _ => Err(RuntimeError::new(
+            span,
+            String::from("Argument 1 must be a list."),
+        )),
+    }
+}
+
+fn map_argument(value: &Value, span: Span) -> RuntimeResult<Rc<RefCell<LoxMap<Value>>>> {
+    match value {
+        Value::Map(map) => Ok(map.clone()),
+        _ => Err(RuntimeError::new(
+            span,
+            String::from("Argument 1 must be a map."),
+        )),
+    }
+}
+
+fn check_length(budget: &Budget, len: usize, span: Span) -> RuntimeResult<()> {
+    budget
+        .check_length(len)
+        .map_err(|limit| budget.error(limit, span))
+}
+
+fn len(_: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let len = match &args[0] {
+        Value::Map(map) => map.borrow().len(),
+        Value::String(text) => text.chars().count(),
+        other => list_argument(other, span)?.borrow().len(),
+    };
+    Ok(Value::Number(len as f64))
+}
+
+fn push(_: &mut Heap, budget: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let list = list_argument(&args[0], span)?;
+    check_length(budget, list.borrow().len() + 1, span)?;
+    list.borrow_mut().push(args[1].clone());
+    Ok(Value::Nil)
+}
+
+fn pop(_: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let list = list_argument(&args[0], span)?;
+    let last = list.borrow_mut().pop();
+    last.ok_or_else(|| RuntimeError::new(span, String::from("Can't pop from an empty list.")))
+}
+
+fn insert(_: &mut Heap, budget: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let list = list_argument(&args[0], span)?;
+    let len = list.borrow().len();
+    let position = list::boundary_index(args[1].as_number(), len, span)?;
+    check_length(budget, len + 1, span)?;
+    list.borrow_mut().insert(position, args[2].clone());
+    Ok(Value::Nil)
+}
+
+fn remove(_: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let list = list_argument(&args[0], span)?;
+    let len = list.borrow().len();
+    let position = list::element_index(args[1].as_number(), len, span)?;
+    let removed = list.borrow_mut().remove(position);
+    Ok(removed)
+}
+
+fn keys(heap: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let map = map_argument(&args[0], span)?;
+    let keys = map
+        .borrow()
+        .iter()
+        .map(|(key, _)| Value::from_key(key))
+        .collect();
+    let keys = Rc::new(RefCell::new(keys));
+    heap.track(&keys);
+    Ok(Value::List(keys))
+}
+
+fn values(heap: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let map = map_argument(&args[0], span)?;
+    let values = map
+        .borrow()
+        .iter()
+        .map(|(_, value)| value.clone())
+        .collect();
+    let values = Rc::new(RefCell::new(values));
+    heap.track(&values);
+    Ok(Value::List(values))
+}
+
+fn has(_: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let map = map_argument(&args[0], span)?;
+    let key = args[1].to_key(span)?;
+    let has = map.borrow().contains_key(&key);
+    Ok(Value::Boolean(has))
+}
+
+fn delete(_: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let map = map_argument(&args[0], span)?;
+    let key = args[1].to_key(span)?;
+    let removed = map.borrow_mut().remove(&key);
+    Ok(Value::Boolean(removed.is_some()))
+}
+
+fn type_name(_: &mut Heap, _: &Budget, args: &[Value], _: Span) -> RuntimeResult<Value> {
+    let name = match &args[0] {
+        Value::String(_) => "string",
+        Value::Number(_) => "number",
+        Value::Boolean(_) => "boolean",
+        Value::Nil => "nil",
+        Value::Function(_)
+        | Value::Closure(_)
+        | Value::Native(_)
+        | Value::HostNative(_)
+        | Value::BoundMethod(_)
+        | Value::BoundNative(_) => "function",
+        Value::Class(_) => "class",
+        Value::Instance(_) => "instance",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+    };
+    Ok(Value::String(Rc::from(name)))
+}
+
+fn instanceof(_: &mut Heap, _: &Budget, args: &[Value], span: Span) -> RuntimeResult<Value> {
+    let class = match &args[1] {
+        Value::Class(class) => class,
+        _ => {
+            return Err(RuntimeError::new(
+                span,
+                String::from("Argument 2 must be a class."),
+            ))
+        }
+    };
+    let is_instance = match &args[0] {
+        Value::Instance(instance) => instance.class.is_subclass_of(class),
+        _ => false,
+    };
+    Ok(Value::Boolean(is_instance))
+}
+
+fn gc(heap: &mut Heap, _: &Budget, _: &[Value], _: Span) -> RuntimeResult<Value> {
+    let collected = heap.collect();
+    let mut map = LoxMap::new();
+    for (key, value) in heap.stats().report(collected) {
+        map.insert(MapKey::String(key.to_string()), Value::Number(value));
+    }
+    let map = Rc::new(RefCell::new(map));
+    heap.track(&map);
+    Ok(Value::Map(map))
+}
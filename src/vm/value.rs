@@ -0,0 +1,397 @@
+use super::Chunk;
+use crate::common::{Literal, RuntimeError, RuntimeResult, Span};
+use crate::gc::{Heap, Trace, Tracer};
+use crate::runtime::{HostData, LoxCallable, LoxMap, MapKey, NativeFunction, NativeMethod};
+use crate::sandbox::Budget;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::rc::Rc;
+
+/// A value on the VM stack. Scalars behave exactly like their `Literal`
+/// counterparts in the tree-walker; heap objects are the VM's own.
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(Rc<str>),
+    Number(f64),
+    Boolean(bool),
+    Function(Rc<FunctionProto>),
+    Closure(Rc<Closure>),
+    Native(Rc<Native>),
+    /// A native defined by the embedder through the tree-walker's API.
+    HostNative(Rc<NativeFunction>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    BoundMethod(Rc<BoundMethod>),
+    /// A method of a class defined by the embedder, bound to an instance.
+    BoundNative(Rc<BoundNative>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<LoxMap<Value>>>),
+    Nil,
+}
+
+impl Value {
+    /// Converts the literals the parser produces, and lists and maps of them
+    /// handed in by an embedder, which are copied. Functions, classes and
+    /// instances of the tree-walker have no bytecode representation.
+    pub fn from_literal(literal: &Literal) -> Option<Value> {
+        match literal {
+            Literal::String(s) => Some(Value::String(Rc::from(s.as_str()))),
+            Literal::Number(n) => Some(Value::Number(*n)),
+            Literal::Boolean(b) => Some(Value::Boolean(*b)),
+            Literal::List(list) => {
+                let elements = list
+                    .borrow()
+                    .iter()
+                    .map(Value::from_literal)
+                    .collect::<Option<Vec<Value>>>()?;
+                Some(Value::List(Rc::new(RefCell::new(elements))))
+            }
+            Literal::Map(map) => {
+                let mut entries = LoxMap::new();
+                for (key, value) in map.borrow().iter() {
+                    entries.insert(key.clone(), Value::from_literal(value)?);
+                }
+                Some(Value::Map(Rc::new(RefCell::new(entries))))
+            }
+            Literal::Nil => Some(Value::Nil),
+            _ => None,
+        }
+    }
+
+    /// The inverse of `from_literal`, used to hand values to an embedder.
+    pub fn to_literal(&self) -> Option<Literal> {
+        match self {
+            Value::String(s) => Some(Literal::String(s.to_string())),
+            Value::Number(n) => Some(Literal::Number(*n)),
+            Value::Boolean(b) => Some(Literal::Boolean(*b)),
+            Value::List(list) => {
+                let elements = list
+                    .borrow()
+                    .iter()
+                    .map(Value::to_literal)
+                    .collect::<Option<Vec<Literal>>>()?;
+                Some(Literal::List(Rc::new(RefCell::new(elements))))
+            }
+            Value::Map(map) => {
+                let mut entries = LoxMap::new();
+                for (key, value) in map.borrow().iter() {
+                    entries.insert(key.clone(), value.to_literal()?);
+                }
+                Some(Literal::Map(Rc::new(RefCell::new(entries))))
+            }
+            Value::Nil => Some(Literal::Nil),
+            _ => None,
+        }
+    }
+
+    pub fn from_key(key: &MapKey) -> Value {
+        match key {
+            MapKey::String(s) => Value::String(Rc::from(s.as_str())),
+            MapKey::Number(n) => Value::Number(*n),
+            MapKey::Boolean(b) => Value::Boolean(*b),
+            MapKey::Nil => Value::Nil,
+        }
+    }
+
+    pub fn to_key(&self, span: Span) -> RuntimeResult<MapKey> {
+        match self {
+            Value::String(s) => Ok(MapKey::String(s.to_string())),
+            Value::Number(n) if !n.is_nan() => Ok(MapKey::Number(*n)),
+            Value::Boolean(b) => Ok(MapKey::Boolean(*b)),
+            Value::Nil => Ok(MapKey::Nil),
+            _ => Err(RuntimeError::new(
+                span,
+                String::from("Map keys must be strings, numbers, booleans or nil."),
+            )),
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Reports the object this value refers to, if any, to the collector.
+    /// Function prototypes are constants that hold no values of their own.
+    pub fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Closure(closure) => tracer.edge(closure),
+            Value::Class(class) => tracer.edge(class),
+            Value::Instance(instance) => tracer.edge(instance),
+            Value::BoundMethod(bound) => tracer.edge(bound),
+            Value::BoundNative(bound) => tracer.edge(bound),
+            Value::List(list) => tracer.edge(list),
+            Value::Map(map) => tracer.edge(map),
+            Value::String(_)
+            | Value::Number(_)
+            | Value::Boolean(_)
+            | Value::Function(_)
+            | Value::Native(_)
+            | Value::HostNative(_)
+            | Value::Nil => {}
+        }
+    }
+
+    /// Same rules as `Literal::is_truthy`.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::String(s) => !s.is_empty(),
+            Value::Number(n) => *n > 0.0,
+            Value::Boolean(b) => *b,
+            Value::List(list) => !list.borrow().is_empty(),
+            Value::Map(map) => !map.borrow().is_empty(),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Function(function) => write!(f, "<fn {}>", function.name),
+            Value::Closure(closure) => write!(f, "<fn {}>", closure.function.name),
+            Value::Native(native) => write!(f, "<fn {}>", native.name),
+            Value::HostNative(native) => write!(f, "<fn {}>", native.name()),
+            Value::Class(class) => write!(f, "class {}", class.name),
+            Value::Instance(instance) => write!(f, "instance {}", instance.class.name),
+            Value::BoundMethod(bound) => write!(f, "<fn {}>", bound.method.function.name),
+            Value::BoundNative(bound) => write!(f, "<fn {}>", bound.method.name()),
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", Value::from_key(key), value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Nil => write!(f, "NIL"),
+        }
+    }
+}
+
+/// A compiled function. It only exists as a constant; the `Closure`
+/// instruction turns it into a callable closure at runtime.
+#[derive(Debug)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /// Keeps the text the function was compiled from alive for the errors
+    /// raised in it. Scripts have none; whoever runs them holds theirs.
+    pub source: Option<Rc<str>>,
+}
+
+/// The global variables of a script or module, shared by the closures
+/// created in it.
+pub type Globals = Rc<RefCell<HashMap<Rc<str>, Value>>>;
+
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    pub globals: Globals,
+}
+
+/// Leaves out the globals, which can contain the closure itself.
+impl Debug for Closure {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Closure")
+            .field("function", &self.function)
+            .field("upvalues", &self.upvalues)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A variable captured by a closure. It points into the stack while the
+/// variable's scope is active and owns the value once the scope has ended.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub type NativeFn = fn(&mut Heap, &Budget, &[Value], Span) -> RuntimeResult<Value>;
+
+/// A function implemented in Rust. It receives the heap to track the objects
+/// it allocates in, its arguments and the span of the call site, which it uses
+/// to report errors.
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "<native {}>", self.name)
+    }
+}
+
+/// Methods written in Lox take precedence over native ones: a class can only
+/// have native methods by inheriting them from a class defined by the
+/// embedder, so any Lox method of the same name overrides them.
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    /// Methods are copied down from it, but `instanceof` still needs it.
+    pub superclass: RefCell<Option<Rc<Class>>>,
+    pub methods: RefCell<HashMap<String, Rc<Closure>>>,
+    pub native_methods: RefCell<HashMap<String, Rc<NativeMethod>>>,
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: RefCell<HashMap<String, Value>>,
+    pub host_data: HostData,
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+#[derive(Debug)]
+pub struct BoundNative {
+    pub receiver: Rc<Instance>,
+    pub method: Rc<NativeMethod>,
+}
+
+impl Trace for Closure {
+    fn trace(&self, tracer: &mut Tracer) {
+        for upvalue in &self.upvalues {
+            tracer.edge(upvalue);
+        }
+    }
+}
+
+/// Open upvalues point into the stack, whose values are roots.
+impl Trace for RefCell<Upvalue> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(upvalue) = self.try_borrow() {
+            if let Upvalue::Closed(value) = &*upvalue {
+                value.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut upvalue) = self.try_borrow_mut() {
+            *upvalue = Upvalue::Closed(Value::Nil);
+        }
+    }
+}
+
+impl Class {
+    /// Whether this is `class` or inherits from it.
+    pub fn is_subclass_of(&self, class: &Class) -> bool {
+        std::ptr::eq(self, class)
+            || self
+                .superclass
+                .borrow()
+                .as_ref()
+                .is_some_and(|superclass| superclass.is_subclass_of(class))
+    }
+}
+
+impl Trace for Class {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(superclass) = self.superclass.try_borrow() {
+            if let Some(superclass) = superclass.as_ref() {
+                tracer.edge(superclass);
+            }
+        }
+        if let Ok(methods) = self.methods.try_borrow() {
+            for method in methods.values() {
+                tracer.edge(method);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut methods) = self.methods.try_borrow_mut() {
+            methods.clear();
+        }
+    }
+}
+
+impl Trace for Instance {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.class);
+        if let Ok(fields) = self.fields.try_borrow() {
+            for value in fields.values() {
+                value.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut fields) = self.fields.try_borrow_mut() {
+            fields.clear();
+        }
+    }
+}
+
+impl Trace for BoundMethod {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.receiver.trace(tracer);
+        tracer.edge(&self.method);
+    }
+}
+
+impl Trace for BoundNative {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.receiver);
+    }
+}
+
+impl Trace for RefCell<Vec<Value>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(list) = self.try_borrow() {
+            for element in list.iter() {
+                element.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut list) = self.try_borrow_mut() {
+            list.clear();
+        }
+    }
+}
+
+impl Trace for RefCell<LoxMap<Value>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Ok(map) = self.try_borrow() {
+            for (_, value) in map.iter() {
+                value.trace(tracer);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        if let Ok(mut map) = self.try_borrow_mut() {
+            map.clear();
+        }
+    }
+}
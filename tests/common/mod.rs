@@ -0,0 +1,50 @@
+//! Helpers shared by the integration tests, which run every program on both
+//! backends.
+
+#![allow(dead_code)]
+
+use rust_lox::{Lox, Sandbox};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// A writer whose contents stay readable after it is handed to the engine.
+#[derive(Clone, Default)]
+pub struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Capture {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An engine for each backend, named for assertion messages.
+pub fn engines() -> [(&'static str, Lox); 2] {
+    [("tree-walker", Lox::new()), ("vm", Lox::with_vm())]
+}
+
+/// A sandboxed engine for each backend.
+pub fn sandboxed(sandbox: &Sandbox) -> [(&'static str, Lox); 2] {
+    [
+        ("tree-walker", Lox::sandboxed(sandbox)),
+        ("vm", Lox::sandboxed_vm(sandbox)),
+    ]
+}
+
+/// Captures what `lox` prints.
+pub fn capture(lox: &mut Lox) -> Capture {
+    let output = Capture::default();
+    lox.set_output(output.clone());
+    output
+}
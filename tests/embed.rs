@@ -0,0 +1,153 @@
+mod common;
+
+use common::{capture, engines, Capture};
+use rust_lox::common::{Sources, Span};
+use rust_lox::{Literal, LoxError};
+
+#[test]
+fn eval_returns_the_value_of_the_last_expression() {
+    for (backend, mut lox) in engines() {
+        let value = lox.eval("var a = 2; a * 21;").unwrap();
+        assert_eq!(value, Literal::Number(42.0), "{}", backend);
+        let value = lox.eval("var b = 1;").unwrap();
+        assert_eq!(value, Literal::Nil, "{}", backend);
+    }
+}
+
+#[test]
+fn evals_share_the_global_scope() {
+    for (backend, mut lox) in engines() {
+        lox.eval("fun twice(x) { return x * 2; }").unwrap();
+        let value = lox.eval("twice(4);").unwrap();
+        assert_eq!(value, Literal::Number(8.0), "{}", backend);
+    }
+}
+
+#[test]
+fn globals_pass_values_in_and_out() {
+    for (backend, mut lox) in engines() {
+        lox.set_global("name", Literal::String(String::from("lox")))
+            .unwrap();
+        lox.eval("var greeting = \"hello \" + name; var list = [1, [2]];")
+            .unwrap();
+        assert_eq!(
+            lox.get_global("greeting"),
+            Some(Literal::String(String::from("hello lox"))),
+            "{}",
+            backend
+        );
+        let list = lox.get_global("list").unwrap();
+        assert_eq!(list.to_string(), "[1, [2]]", "{}", backend);
+        assert_eq!(lox.get_global("missing"), None, "{}", backend);
+    }
+}
+
+#[test]
+fn the_vm_refuses_values_it_cannot_hold() {
+    let mut lox = rust_lox::Lox::with_vm();
+    lox.eval("fun f() {}").unwrap();
+    assert!(lox.get_global("f").is_none());
+    let err = lox.eval("f;").unwrap_err();
+    assert!(matches!(err, LoxError::Runtime { .. }));
+}
+
+#[test]
+fn print_goes_to_the_output() {
+    for (backend, mut lox) in engines() {
+        let output = capture(&mut lox);
+        lox.eval("print 1 + 2; print \"two\";").unwrap();
+        assert_eq!(output.text(), "3\ntwo\n", "{}", backend);
+    }
+}
+
+#[test]
+fn eprint_goes_to_the_error_output() {
+    for (backend, mut lox) in engines() {
+        let output = capture(&mut lox);
+        let errors = Capture::default();
+        lox.set_error_output(errors.clone());
+        lox.eval("print 1; eprint(\"oops\"); eprint([2]);").unwrap();
+        assert_eq!(output.text(), "1\n", "{}", backend);
+        assert_eq!(errors.text(), "oops\n[2]\n", "{}", backend);
+    }
+}
+
+#[test]
+fn errors_map_to_exit_codes() {
+    for (backend, mut lox) in engines() {
+        let err = lox.eval("print 1 +;").unwrap_err();
+        assert!(matches!(err, LoxError::Compile { .. }), "{}", backend);
+        assert_eq!(err.exit_code(), 65, "{}", backend);
+
+        let err = lox.eval("print 1 + nil;").unwrap_err();
+        assert!(matches!(err, LoxError::Runtime { .. }), "{}", backend);
+        assert_eq!(err.exit_code(), 70, "{}", backend);
+
+        let err = lox.eval_file("does/not/exist.lox").unwrap_err();
+        assert!(matches!(err, LoxError::Io { .. }), "{}", backend);
+        assert_eq!(err.exit_code(), 66, "{}", backend);
+    }
+}
+
+#[test]
+fn reports_quote_the_source() {
+    for (backend, mut lox) in engines() {
+        lox.eval("fun f() {\n  return nil.x;\n}").unwrap();
+        let report = lox.eval("f();").unwrap_err().to_string();
+        assert!(
+            report.starts_with("[line 2 ] Error at runtime : Only instances have properties."),
+            "{}: {}",
+            backend,
+            report
+        );
+        assert!(report.contains("2 |   return nil.x;"), "{}", backend);
+        assert!(report.contains("    at f (line 2)"), "{}", backend);
+    }
+}
+
+#[test]
+fn errors_in_modules_name_their_file() {
+    for (backend, mut lox) in engines() {
+        capture(&mut lox);
+        let report = lox
+            .eval_file("test/modules/import-runtime-error.lox")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            report.contains(" --> test/modules/lib/failing.lox:2:10"),
+            "{}: {}",
+            backend,
+            report
+        );
+
+        let report = lox
+            .eval_file("test/modules/import-compile-error.lox")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            report.contains(" --> test/modules/lib/broken.lox:2:5"),
+            "{}: {}",
+            backend,
+            report
+        );
+        assert!(
+            report.contains(" --> test/modules/import-compile-error.lox:2:8"),
+            "{}: {}",
+            backend,
+            report
+        );
+    }
+}
+
+#[test]
+fn sources_are_dropped_once_nothing_holds_them() {
+    let mut sources = Sources::new();
+    let (kept, kept_offset) = sources.add("print 1;", None);
+    let (dropped, dropped_offset) = sources.add("print 2;", None);
+    drop(dropped);
+    let span = |offset| Span::new(offset + 6, offset + 7, 1, 7);
+    assert!(sources.render(span(kept_offset)).contains("1 | print 1;"));
+    assert_eq!(sources.render(span(dropped_offset)), "");
+    drop(kept);
+    assert_eq!(sources.render(span(kept_offset)), "");
+}
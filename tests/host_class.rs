@@ -0,0 +1,126 @@
+mod common;
+
+use common::{capture, engines};
+use rust_lox::runtime::NativeClass;
+use rust_lox::{Literal, LoxError};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// The host data of each `Counter` instance.
+struct Counter {
+    count: Cell<f64>,
+}
+
+fn counter_class(forgotten: Rc<Cell<usize>>) -> NativeClass {
+    NativeClass::new("Counter")
+        .method("init", 1, |this, args| {
+            this.set_data(Counter {
+                count: Cell::new(args.get(0)?),
+            });
+            Ok(Literal::Nil)
+        })
+        .method("increment", 0, |this, _| {
+            let counter = this.data::<Counter>()?;
+            counter.count.set(counter.count.get() + 1.0);
+            Ok(Literal::Number(counter.count.get()))
+        })
+        .method("count", 0, |this, _| {
+            Ok(Literal::Number(this.data::<Counter>()?.count.get()))
+        })
+        .method("forget", 0, move |this, _| {
+            this.set_data(());
+            forgotten.set(forgotten.get() + 1);
+            Ok(Literal::Nil)
+        })
+}
+
+#[test]
+fn instances_carry_their_own_host_data() {
+    for (backend, mut lox) in engines() {
+        let output = capture(&mut lox);
+        lox.define_class(counter_class(Rc::default()));
+        lox.eval(
+            "var a = Counter(1);
+             var b = Counter(10);
+             a.increment();
+             a.increment();
+             b.increment();
+             print a.count();
+             print b.count();
+             print a;",
+        )
+        .unwrap();
+        assert_eq!(output.text(), "3\n11\ninstance Counter\n", "{}", backend);
+    }
+}
+
+#[test]
+fn subclasses_inherit_native_methods() {
+    for (backend, mut lox) in engines() {
+        lox.define_class(counter_class(Rc::default()));
+        let value = lox
+            .eval(
+                "class Steps < Counter {
+                   init(start) {
+                     super.init(start);
+                     this.label = \"steps\";
+                   }
+                   count() { return this.label + \": \" + super.count(); }
+                 }
+                 var steps = Steps(5);
+                 steps.increment();
+                 steps.count();",
+            )
+            .unwrap();
+        assert_eq!(
+            value,
+            Literal::String(String::from("steps: 6")),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn methods_fail_without_host_data() {
+    for (backend, mut lox) in engines() {
+        let forgotten = Rc::new(Cell::new(0));
+        lox.define_class(counter_class(forgotten.clone()));
+        let cases = [
+            "class Broken < Counter { init() {} } Broken().count();",
+            "var c = Counter(1); c.forget(); c.count();",
+        ];
+        for source in cases {
+            match lox.eval(source) {
+                Err(LoxError::Runtime { error, .. }) => assert_eq!(
+                    error.message, "This Counter instance was not initialized.",
+                    "{}: {}",
+                    backend, source
+                ),
+                other => panic!("{}: {} gave {:?}", backend, source, other.map(|_| ())),
+            }
+        }
+        assert_eq!(forgotten.get(), 1, "{}", backend);
+    }
+}
+
+#[test]
+fn init_checks_its_arguments() {
+    for (backend, mut lox) in engines() {
+        lox.define_class(counter_class(Rc::default()));
+        let err = lox.eval("Counter(\"one\");").unwrap_err();
+        assert!(
+            err.to_string().contains("Argument 1 must be a number."),
+            "{}: {}",
+            backend,
+            err
+        );
+        let err = lox.eval("Counter();").unwrap_err();
+        assert!(
+            err.to_string().contains("Expected 1 arguments but got 0."),
+            "{}: {}",
+            backend,
+            err
+        );
+    }
+}
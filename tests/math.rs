@@ -0,0 +1,53 @@
+mod common;
+
+use common::engines;
+use rust_lox::{Literal, LoxError};
+
+const UNARY: [&str; 15] = [
+    "sqrt", "abs", "floor", "ceil", "round", "sin", "cos", "tan", "asin", "acos", "atan", "exp",
+    "log", "log10", "log2",
+];
+
+fn error_message(result: Result<Literal, LoxError>) -> String {
+    match result {
+        Err(LoxError::Runtime { error, .. }) => error.message,
+        other => panic!("expected a runtime error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn functions_compute_on_numbers() {
+    for (backend, mut lox) in engines() {
+        let value = lox
+            .eval("sqrt(16) + abs(-1) + floor(1.5) + pow(2, 3) + max(1, 5, 3) + min(4, 2);")
+            .unwrap();
+        assert_eq!(value, Literal::Number(21.0), "{}", backend);
+        let value = lox.eval("round(PI * 100) / 100;").unwrap();
+        assert_eq!(value, Literal::Number(3.14), "{}", backend);
+    }
+}
+
+#[test]
+fn arguments_must_be_numbers() {
+    for (backend, mut lox) in engines() {
+        for name in UNARY {
+            let message = error_message(lox.eval(&format!("{}(\"x\");", name)));
+            assert_eq!(
+                message, "Argument 1 must be a number.",
+                "{}: {}",
+                backend, name
+            );
+        }
+        let cases = [
+            ("pow(\"2\", 3);", "Argument 1 must be a number."),
+            ("pow(2, nil);", "Argument 2 must be a number."),
+            ("atan2(1, true);", "Argument 2 must be a number."),
+            ("min(1, 2, [3]);", "Argument 3 must be a number."),
+            ("max(\"1\");", "Argument 1 must be a number."),
+        ];
+        for (source, expected) in cases {
+            let message = error_message(lox.eval(source));
+            assert_eq!(message, expected, "{}: {}", backend, source);
+        }
+    }
+}
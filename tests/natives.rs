@@ -0,0 +1,134 @@
+mod common;
+
+use common::{capture, engines};
+use rust_lox::runtime::native::{ListRef, MapRef};
+use rust_lox::runtime::{Arity, NativeModule};
+use rust_lox::{Literal, LoxError};
+
+#[test]
+fn natives_receive_and_return_values() {
+    for (backend, mut lox) in engines() {
+        lox.define_native("sum", Arity::AtLeast(0), |args| {
+            Ok(Literal::Number(args.rest::<f64>(0)?.iter().sum()))
+        });
+        lox.define_native("greet", 1, |args| {
+            Ok(Literal::String(format!("hi {}", args.get::<String>(0)?)))
+        });
+        let value = lox.eval("sum(1, 2, 3) + len(greet(\"x\"));").unwrap();
+        assert_eq!(value, Literal::Number(10.0), "{}", backend);
+    }
+}
+
+#[test]
+fn arguments_of_the_wrong_type_are_errors() {
+    let cases = [
+        ("number(\"x\");", "Argument 1 must be a number."),
+        ("integer(1.5);", "Argument 1 must be an integer."),
+        ("string(1, 2);", "Argument 2 must be a string."),
+        ("list(nil);", "Argument 1 must be a list."),
+        ("optional(true);", "Argument 1 must be a map or nil."),
+    ];
+    for (backend, mut lox) in engines() {
+        let module = NativeModule::new("checks")
+            .function("number", 1, |args| args.get::<f64>(0).map(|_| Literal::Nil))
+            .function("integer", 1, |args| {
+                args.get::<i64>(0).map(|_| Literal::Nil)
+            })
+            .function("string", 2, |args| {
+                args.get::<String>(1).map(|_| Literal::Nil)
+            })
+            .function("list", 1, |args| {
+                args.get::<ListRef>(0).map(|_| Literal::Nil)
+            })
+            .function("optional", 1, |args| {
+                args.get::<Option<MapRef>>(0).map(|_| Literal::Nil)
+            });
+        lox.define_module(module).unwrap();
+        for (source, message) in cases {
+            match lox.eval(source) {
+                Err(LoxError::Runtime { error, .. }) => {
+                    assert_eq!(error.message, message, "{}: {}", backend, source)
+                }
+                other => panic!("{}: {} gave {:?}", backend, source, other.map(|_| ())),
+            }
+        }
+    }
+}
+
+#[test]
+fn arity_is_checked_before_the_call() {
+    for (backend, mut lox) in engines() {
+        lox.define_native("one", 1, |_| Ok(Literal::Nil));
+        let err = lox.eval("one(1, 2);").unwrap_err();
+        assert!(
+            err.to_string().contains("Expected 1 arguments but got 2."),
+            "{}: {}",
+            backend,
+            err
+        );
+    }
+}
+
+#[test]
+fn changes_to_lists_and_maps_are_shared() {
+    for (backend, mut lox) in engines() {
+        let output = capture(&mut lox);
+        lox.define_native("append", 2, |args| {
+            args.get::<ListRef>(0)?
+                .borrow_mut()
+                .push(args.value(1).clone());
+            Ok(Literal::Nil)
+        });
+        lox.define_native("count", 2, |args| {
+            let map = args.get::<MapRef>(0)?;
+            let key = rust_lox::runtime::MapKey::String(args.get(1)?);
+            let count = match map.borrow().get(&key) {
+                Some(Literal::Number(n)) => n,
+                _ => 0.0,
+            };
+            map.borrow_mut().insert(key, Literal::Number(count + 1.0));
+            Ok(Literal::Nil)
+        });
+        lox.eval(
+            "var inner = [1];
+             var outer = [inner, inner];
+             append(inner, 2);
+             append(outer[1], [3]);
+             print outer;
+             var counts = {};
+             count(counts, \"a\");
+             count(counts, \"a\");
+             print counts;",
+        )
+        .unwrap();
+        assert_eq!(
+            output.text(),
+            "[[1, 2, [3]], [1, 2, [3]]]\n{a: 2}\n",
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn values_pass_through_natives_unchanged() {
+    for (backend, mut lox) in engines() {
+        let output = capture(&mut lox);
+        lox.define_native("identity", 1, |args| Ok(args.value(0).clone()));
+        lox.define_native("first", 1, |args| {
+            let list = args.get::<ListRef>(0)?;
+            let first = list.borrow().first().cloned();
+            Ok(first.unwrap_or(Literal::Nil))
+        });
+        lox.eval(
+            "class Point { init(x) { this.x = x; } }
+             fun double(n) { return n * 2; }
+             print identity(double)(4);
+             print first([Point(5)]).x;
+             print identity(Point)(6).x;
+             print identity(len)([1, 2]);",
+        )
+        .unwrap();
+        assert_eq!(output.text(), "8\n5\n6\n2\n", "{}", backend);
+    }
+}
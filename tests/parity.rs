@@ -0,0 +1,101 @@
+//! Runs every script under `test/` on both backends, which must print the
+//! same output and fail the same way, and compares the output with the
+//! `.out` file next to the script. Paths are relative to the root of the
+//! crate, where `cargo test` runs. Setting `LOX_BLESS` rewrites the `.out`
+//! files from what the tree-walker prints.
+
+mod common;
+
+use common::{engines, Capture};
+use rust_lox::{Literal, LoxError};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Scripts that read standard input, which the tests can't provide.
+const SKIPPED: [&str; 1] = ["read-line.lox"];
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+/// What the script prints, followed by the report of the error stopping it,
+/// as the command line interpreter shows them.
+fn run(script: &Path) -> Vec<(&'static str, String, Option<LoxError>)> {
+    engines()
+        .into_iter()
+        .map(|(backend, mut lox)| {
+            let output = Capture::default();
+            lox.set_output(output.clone());
+            lox.set_error_output(output.clone());
+            lox.set_global("args", Literal::List(Rc::new(RefCell::new(vec![]))))
+                .unwrap();
+            let result = lox.eval_file(script);
+            let mut transcript = output.text();
+            if let Err(err) = &result {
+                transcript.push_str(&format!("{}\n[exit {}]\n", err, err.exit_code()));
+            }
+            (backend, transcript, result.err())
+        })
+        .collect()
+}
+
+#[test]
+fn backends_agree_on_every_test_script() {
+    let root = Path::new("test");
+    let mut found = vec![];
+    scripts(root, &mut found);
+    found.sort();
+    assert!(!found.is_empty());
+    let bless = env::var_os("LOX_BLESS").is_some();
+
+    for script in found {
+        let name = script.file_name().unwrap().to_str().unwrap();
+        if SKIPPED.contains(&name) {
+            continue;
+        }
+        let runs = run(&script);
+        let (_, expected, expected_error) = &runs[0];
+        for (backend, transcript, error) in &runs[1..] {
+            assert_eq!(
+                transcript,
+                expected,
+                "{} prints differently on the {}",
+                script.display(),
+                backend
+            );
+            assert_eq!(
+                error.as_ref().map(mem::discriminant),
+                expected_error.as_ref().map(mem::discriminant),
+                "{} fails differently on the {}",
+                script.display(),
+                backend
+            );
+        }
+
+        let out = script.with_extension("out");
+        if bless {
+            fs::write(&out, expected).unwrap();
+        } else {
+            let recorded =
+                fs::read_to_string(&out).unwrap_or_else(|_| panic!("{} is missing", out.display()));
+            assert_eq!(
+                expected,
+                &recorded,
+                "{} doesn't print {}",
+                script.display(),
+                out.display()
+            );
+        }
+    }
+}
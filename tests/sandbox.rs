@@ -0,0 +1,208 @@
+mod common;
+
+use common::{engines, sandboxed};
+use rust_lox::sandbox::MAX_CALL_DEPTH;
+use rust_lox::{Limit, Literal, LoxError, Sandbox};
+use std::time::Duration;
+
+const RUNAWAY: &str = "fun f(n) { return f(n + 1); } f(0);";
+
+fn limit_of(result: Result<Literal, LoxError>) -> Option<Limit> {
+    match result {
+        Err(LoxError::Limit { error, .. }) => error.limit,
+        other => panic!("expected a limit error, got {:?}", other),
+    }
+}
+
+#[test]
+fn runaway_recursion_stops_with_a_limit_error() {
+    for (backend, mut lox) in engines() {
+        let result = lox.eval(RUNAWAY);
+        let err = result.as_ref().unwrap_err();
+        assert_eq!(err.exit_code(), 70, "{}", backend);
+        assert!(
+            err.to_string()
+                .contains(&format!("Call depth limit of {} exceeded.", MAX_CALL_DEPTH)),
+            "{}: {}",
+            backend,
+            err
+        );
+        assert_eq!(limit_of(result), Some(Limit::CallDepth), "{}", backend);
+    }
+}
+
+#[test]
+fn recursion_up_to_the_limit_runs() {
+    let source = format!(
+        "fun down(n) {{ if (n > 1) return down(n - 1); return n; }} down({});",
+        MAX_CALL_DEPTH
+    );
+    for (backend, mut lox) in engines() {
+        let value = lox.eval(&source).unwrap();
+        assert_eq!(value, Literal::Number(1.0), "{}", backend);
+    }
+}
+
+#[test]
+fn sandboxes_limit_call_depth_by_default() {
+    for (backend, mut lox) in sandboxed(&Sandbox::new()) {
+        let err = lox.eval(RUNAWAY).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Call depth limit of 100 exceeded."),
+            "{}: {}",
+            backend,
+            err
+        );
+    }
+}
+
+#[test]
+fn call_depths_beyond_the_cap_are_lowered() {
+    let sandbox = Sandbox::new().max_call_depth(usize::MAX);
+    for (backend, mut lox) in sandboxed(&sandbox) {
+        let err = lox.eval(RUNAWAY).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(&format!("Call depth limit of {} exceeded.", MAX_CALL_DEPTH)),
+            "{}: {}",
+            backend,
+            err
+        );
+    }
+}
+
+#[test]
+fn only_allowed_modules_are_defined() {
+    let sandbox = Sandbox::new().allow_module("math");
+    for (backend, mut lox) in sandboxed(&sandbox) {
+        assert_eq!(
+            lox.eval("sqrt(16);").unwrap(),
+            Literal::Number(4.0),
+            "{}",
+            backend
+        );
+        let err = lox.eval("upper(\"a\");").unwrap_err();
+        assert!(
+            err.to_string().contains("Undefined upper variable."),
+            "{}: {}",
+            backend,
+            err
+        );
+    }
+}
+
+#[test]
+fn imports_are_refused_unless_allowed() {
+    for (backend, mut lox) in sandboxed(&Sandbox::new()) {
+        let err = lox.eval("import \"missing.lox\";").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Imports are not allowed in this sandbox."),
+            "{}: {}",
+            backend,
+            err
+        );
+    }
+}
+
+#[test]
+fn each_limit_stops_the_program() {
+    let cases = [
+        (
+            Sandbox::new().max_instructions(1_000),
+            "while (true) {}",
+            Limit::Instructions,
+        ),
+        (Sandbox::new().max_call_depth(10), RUNAWAY, Limit::CallDepth),
+        (
+            Sandbox::new().max_time(Duration::from_millis(10)),
+            "while (true) {}",
+            Limit::Time,
+        ),
+        (
+            Sandbox::new().max_allocations(100),
+            "var all = []; while (true) { var list = [all]; all = list; }",
+            Limit::Allocations,
+        ),
+    ];
+    for (sandbox, source, limit) in cases {
+        for (backend, mut lox) in sandboxed(&sandbox) {
+            assert_eq!(
+                limit_of(lox.eval(source)),
+                Some(limit),
+                "{}: {}",
+                backend,
+                source
+            );
+        }
+    }
+}
+
+#[test]
+fn try_does_not_catch_limit_errors() {
+    let sandbox = Sandbox::new().max_instructions(1_000);
+    for (backend, mut lox) in sandboxed(&sandbox) {
+        let source = "var caught = false; try { while (true) {} } catch (e) { caught = true; }";
+        assert_eq!(
+            limit_of(lox.eval(source)),
+            Some(Limit::Instructions),
+            "{}",
+            backend
+        );
+        assert_eq!(
+            lox.get_global("caught"),
+            Some(Literal::Boolean(false)),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn each_eval_gets_a_new_budget() {
+    let sandbox = Sandbox::new().max_instructions(1_000);
+    for (backend, mut lox) in sandboxed(&sandbox) {
+        for _ in 0..5 {
+            let value = lox.eval("var n = 0; while (n < 10) n = n + 1; n;").unwrap();
+            assert_eq!(value, Literal::Number(10.0), "{}", backend);
+        }
+    }
+}
+
+#[test]
+fn growing_strings_and_lists_count_against_the_allocation_limit() {
+    let sandbox = Sandbox::new()
+        .allow_module("core")
+        .allow_module("string")
+        .max_instructions(1_000_000)
+        .max_allocations(10);
+    let sources = [
+        "var s = \"x\"; while (true) s = s + s;",
+        "var s = \"x\"; while (true) s = join([s, s], \"\");",
+        "var s = \"xx\"; while (true) s = replace(s, \"x\", s);",
+        "var l = []; var n = 0; while (true) { push(l, n); n = n + 1; }",
+        "var m = {}; var n = 0; while (true) { m[n] = n; n = n + 1; }",
+    ];
+    for source in sources {
+        for (backend, mut lox) in sandboxed(&sandbox) {
+            let result = lox.eval(source);
+            if let Err(err) = &result {
+                assert!(
+                    err.to_string().contains("Allocation limit"),
+                    "{}: {}: {}",
+                    backend,
+                    source,
+                    err
+                );
+            }
+            assert_eq!(
+                limit_of(result),
+                Some(Limit::Allocations),
+                "{}: {}",
+                backend,
+                source
+            );
+        }
+    }
+}